@@ -0,0 +1,290 @@
+use crate::Delay;
+use futures::{task, Async, Future, Poll};
+use std::io::Error as IoError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A future returned by `Clock::delay`.
+pub type ClockDelay = Box<dyn Future<Item = (), Error = IoError> + Send>;
+
+/// A source of time for timers.
+///
+/// `Delay::new_with_clock_source` and `Interval::new_with_clock_source` use a
+/// `Clock` to wait for their deadlines.
+/// `TimerFdClock` waits in the kernel, while `TestClock` is advanced by hand
+/// so timer-dependent logic can be tested without sleeping.
+pub trait Clock: Send + Sync {
+    /// Returns the current time of this clock.
+    fn now(&self) -> Instant;
+
+    /// Returns a future that completes once the clock reaches `deadline`.
+    fn delay(&self, deadline: Instant) -> ClockDelay;
+}
+
+/// The real clock, backed by a `CLOCK_MONOTONIC` timerfd.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimerFdClock;
+
+impl Clock for TimerFdClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn delay(&self, deadline: Instant) -> ClockDelay {
+        Box::new(Delay::lazy(deadline))
+    }
+}
+
+struct TestClockInner {
+    now: Instant,
+    /// Waiting delays by id, with their deadline and task.
+    sleepers: Vec<(usize, Instant, task::Task)>,
+    next_id: usize,
+}
+
+/// A clock that only moves when told to.
+///
+/// Delays created from a `TestClock` complete once `advance` moves the clock
+/// past their deadline, without waiting in real time. `TestClock` is a cheap
+/// handle; clones share the same time.
+#[derive(Clone)]
+pub struct TestClock {
+    inner: Arc<Mutex<TestClockInner>>,
+}
+
+impl TestClock {
+    /// Create a new `TestClock` set to the current instant.
+    pub fn new() -> TestClock {
+        TestClock {
+            inner: Arc::new(Mutex::new(TestClockInner {
+                now: Instant::now(),
+                sleepers: Vec::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// Move the clock forward by `duration`, waking every delay whose
+    /// deadline has been reached.
+    pub fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.now += duration;
+        let now = inner.now;
+        inner.sleepers.retain(|(_, deadline, task)| {
+            if *deadline <= now {
+                task.notify();
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> TestClock {
+        TestClock::new()
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    fn delay(&self, deadline: Instant) -> ClockDelay {
+        let id = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.next_id += 1;
+            inner.next_id
+        };
+        Box::new(TestDelay {
+            clock: self.clone(),
+            id,
+            deadline,
+        })
+    }
+}
+
+struct TestDelay {
+    clock: TestClock,
+    id: usize,
+    deadline: Instant,
+}
+
+impl Future for TestDelay {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut inner = self.clock.inner.lock().unwrap();
+        if inner.now >= self.deadline {
+            return Ok(Async::Ready(()));
+        }
+        // Replace the task of an earlier poll rather than waking it too.
+        let task = task::current();
+        match inner.sleepers.iter_mut().find(|(id, ..)| *id == self.id) {
+            Some(sleeper) => sleeper.2 = task,
+            None => inner.sleepers.push((self.id, self.deadline, task)),
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl Drop for TestDelay {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.clock.inner.lock() {
+            inner.sleepers.retain(|(id, ..)| *id != self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interval;
+    use futures::executor::{self, Notify};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[derive(Default)]
+    struct Flag(AtomicBool);
+
+    impl Notify for Flag {
+        fn notify(&self, _id: usize) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_clock_fires_delay_without_waiting() {
+        let clock = TestClock::new();
+        let start = Instant::now();
+        let deadline = clock.now() + Duration::from_secs(3600);
+        let delay = Delay::new_with_clock_source(Arc::new(clock.clone()), deadline);
+        let mut delay = executor::spawn(delay);
+        let flag = Arc::new(Flag::default());
+
+        let poll = delay.poll_future_notify(&flag, 0).unwrap();
+        assert!(poll.is_not_ready());
+        clock.advance(Duration::from_secs(1800));
+        assert!(!flag.0.load(Ordering::SeqCst));
+        clock.advance(Duration::from_secs(1800));
+        assert!(flag.0.load(Ordering::SeqCst));
+        let poll = delay.poll_future_notify(&flag, 0).unwrap();
+        assert!(poll.is_ready());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn repeated_polls_register_once() {
+        let clock = TestClock::new();
+        let deadline = clock.now() + Duration::from_secs(1);
+        let mut delay = executor::spawn(clock.delay(deadline));
+        let flag = Arc::new(Flag::default());
+        for _ in 0..3 {
+            assert!(delay.poll_future_notify(&flag, 0).unwrap().is_not_ready());
+        }
+        assert_eq!(clock.inner.lock().unwrap().sleepers.len(), 1);
+        drop(delay);
+        assert!(clock.inner.lock().unwrap().sleepers.is_empty());
+    }
+
+    #[test]
+    fn test_clock_drives_interval() {
+        let clock = TestClock::new();
+        let period = Duration::from_secs(60);
+        let interval = Interval::new_with_clock_source(Arc::new(clock.clone()), period);
+        let mut interval = executor::spawn(interval);
+        let flag = Arc::new(Flag::default());
+
+        assert!(interval
+            .poll_stream_notify(&flag, 0)
+            .unwrap()
+            .is_not_ready());
+        // Missed ticks are coalesced into one.
+        clock.advance(period * 5 / 2);
+        assert!(flag.0.swap(false, Ordering::SeqCst));
+        let tick = interval.poll_stream_notify(&flag, 0).unwrap();
+        assert_eq!(tick, Async::Ready(Some(())));
+        assert!(interval
+            .poll_stream_notify(&flag, 0)
+            .unwrap()
+            .is_not_ready());
+        // The schedule keeps its phase: the next tick is at 3 periods.
+        clock.advance(period / 4);
+        assert!(!flag.0.load(Ordering::SeqCst));
+        clock.advance(period / 4);
+        assert!(flag.0.load(Ordering::SeqCst));
+        let tick = interval.poll_stream_notify(&flag, 0).unwrap();
+        assert_eq!(tick, Async::Ready(Some(())));
+    }
+
+    #[test]
+    fn reset_at_reschedules_clock_source_interval() {
+        let clock = TestClock::new();
+        let period = Duration::from_secs(60);
+        let interval = Interval::new_with_clock_source(Arc::new(clock.clone()), period);
+        let mut interval = executor::spawn(interval);
+        let flag = Arc::new(Flag::default());
+
+        assert!(interval
+            .poll_stream_notify(&flag, 0)
+            .unwrap()
+            .is_not_ready());
+        let at = clock.now() + Duration::from_secs(10);
+        interval.get_mut().reset_at(at);
+        // The waiting task is woken to wait for the new schedule.
+        assert!(flag.0.swap(false, Ordering::SeqCst));
+        assert!(interval
+            .poll_stream_notify(&flag, 0)
+            .unwrap()
+            .is_not_ready());
+        assert_eq!(interval.get_ref().next_tick_time(), at);
+        clock.advance(Duration::from_secs(10));
+        assert!(flag.0.swap(false, Ordering::SeqCst));
+        let tick = interval.poll_stream_notify(&flag, 0).unwrap();
+        assert_eq!(tick, Async::Ready(Some(())));
+        assert_eq!(interval.get_ref().next_tick_time(), at + period);
+    }
+
+    #[test]
+    fn prime_leaves_clock_source_interval_without_timerfd() {
+        let clock = TestClock::new();
+        let period = Duration::from_secs(60);
+        let mut interval = Interval::new_with_clock_source(Arc::new(clock.clone()), period);
+        crate::syscalls::take();
+        interval.prime().unwrap();
+        assert_eq!(crate::syscalls::take(), crate::syscalls::Counts::default());
+
+        let mut interval = executor::spawn(interval);
+        let flag = Arc::new(Flag::default());
+        assert!(interval
+            .poll_stream_notify(&flag, 0)
+            .unwrap()
+            .is_not_ready());
+        clock.advance(period);
+        let tick = interval.poll_stream_notify(&flag, 0).unwrap();
+        assert_eq!(tick, Async::Ready(Some(())));
+        assert_eq!(crate::syscalls::take().create, 0);
+    }
+
+    #[test]
+    fn finalize_counts_clock_source_ticks() {
+        let clock = TestClock::new();
+        let period = Duration::from_secs(60);
+        let interval = Interval::new_with_clock_source(Arc::new(clock.clone()), period);
+        let mut interval = executor::spawn(interval);
+        let flag = Arc::new(Flag::default());
+
+        assert!(interval
+            .poll_stream_notify(&flag, 0)
+            .unwrap()
+            .is_not_ready());
+        clock.advance(period * 5 / 2);
+        assert_eq!(interval.get_mut().finalize().unwrap(), 2);
+        // The pending wait on the clock is dropped.
+        assert!(clock.inner.lock().unwrap().sleepers.is_empty());
+        let end = interval.poll_stream_notify(&flag, 0).unwrap();
+        assert_eq!(end, Async::Ready(None));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{Delay, Interval};
+use futures::{Async, Future, Poll, Stream};
+use std::io::Error as IoError;
+use std::time::{Duration, Instant};
+
+/// A stream of the time remaining until a deadline.
+///
+/// Created by [`countdown`].
+///
+/// [`countdown`]: fn.countdown.html
+pub struct Countdown {
+    deadline: Instant,
+    delay: Delay,
+    interval: Interval,
+    done: bool,
+}
+
+/// Create a stream that yields the time remaining until `deadline` every
+/// `update_every`.
+///
+/// The last item is always `Duration::ZERO`, yielded once the deadline is
+/// reached, after which the stream ends.
+///
+/// # Panics
+///
+/// This function panics if `update_every` is zero.
+pub fn countdown(deadline: Instant, update_every: Duration) -> Result<Countdown, IoError> {
+    Ok(Countdown {
+        deadline,
+        delay: Delay::new(deadline)?,
+        interval: Interval::new_interval(update_every)?,
+        done: false,
+    })
+}
+
+impl Stream for Countdown {
+    type Item = Duration;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+        if self.delay.poll()?.is_ready() {
+            self.done = true;
+            return Ok(Async::Ready(Some(Duration::ZERO)));
+        }
+        match self.interval.poll()? {
+            Async::Ready(_) => {
+                let remaining = self.deadline.saturating_duration_since(Instant::now());
+                self.done = remaining.is_zero();
+                Ok(Async::Ready(Some(remaining)))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::current_thread;
+
+    #[test]
+    fn countdown_ends_at_zero() {
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let stream = countdown(deadline, Duration::from_millis(10)).unwrap();
+        let remaining: Vec<_> = current_thread::block_on_all(stream.collect()).unwrap();
+        println!("{:?}", remaining);
+        assert!(remaining.len() >= 5 && remaining.len() <= 11);
+        assert!(remaining.windows(2).all(|pair| pair[0] > pair[1]));
+        assert_eq!(*remaining.last().unwrap(), Duration::ZERO);
+        assert!(Instant::now() >= deadline);
+    }
+}
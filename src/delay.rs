@@ -1,31 +1,349 @@
-use crate::{ClockId, TimerFd};
+use crate::{
+    create, duration_from_nanos, instant_from_system_time, Clock, ClockDelay, ClockId, Inner,
+    RetryAfter, TimerError, TimerFd,
+};
+use futures::future::{self, MapErr, Shared};
+use futures::task::AtomicTask;
 use futures::{task, try_ready, Async, Future};
+use log::warn;
+use std::convert::TryFrom;
 use std::io::Error as IoError;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use timerfd::{SetTimeFlags, TimerState};
+use tokio_reactor::Handle;
+
+/// A `Delay` whose completion is broadcast to all of its clones.
+///
+/// Created by `Delay::shared`.
+pub type SharedDelay = Shared<MapErr<Delay, fn(IoError) -> TimerError>>;
 
 /// A future that completes at a specified instant in time.
 /// Instances of Delay perform no work and complete with () once the specified deadline has been reached.
 /// Delay is powered by `timerfd` and has a resolution of 1 nanosecond.
+///
+/// # Syscalls
+///
+/// Awaiting a `Delay` to completion costs one `timerfd_create`, one
+/// `timerfd_settime`, one `epoll_ctl` when the reactor registers the fd on
+/// first poll and one `read` once the timer fires. Dropping it costs another
+/// `epoll_ctl` to deregister the fd. Every `reset` adds one
+/// `timerfd_settime`. A `Delay` whose deadline has already passed on first
+/// poll completes without arming the timer.
+///
+/// # Executors
+///
+/// A `Delay` polled outside a tokio runtime registers with tokio-reactor's
+/// background reactor, so any executor able to drive a futures 0.1 `Notify`
+/// can await it. Executors built on `std::task` can forward notifications to
+/// their `Waker`.
 pub struct Delay {
-    timerfd: TimerFd,
+    timerfd: Option<TimerFd>,
     deadline: Instant,
+    wall_deadline: Option<SystemTime>,
+    min_arm_duration: Duration,
+    compensation: Duration,
+    yielded: bool,
     initialized: bool,
     task: Option<task::Task>,
+    source: Option<(Arc<dyn Clock>, ClockDelay)>,
+    watchdog: Option<Duration>,
+    strict: bool,
+    remote_reset: Option<Arc<RemoteReset>>,
+}
+
+/// Builds a `Delay` with custom settings.
+#[derive(Debug, Clone, Default)]
+pub struct DelayBuilder {
+    min_arm_duration: Duration,
+}
+
+impl DelayBuilder {
+    /// Create a new builder with the default settings.
+    pub fn new() -> DelayBuilder {
+        DelayBuilder::default()
+    }
+
+    /// Set the shortest duration the timer will be armed for.
+    ///
+    /// If less than `duration` remains until the deadline when the `Delay` is
+    /// polled, the `Delay` doesn't arm its timerfd. Instead it yields to the
+    /// executor and completes on the next poll, as the round trip through the
+    /// reactor would take longer than the remaining time. Such a `Delay` may
+    /// complete up to `duration` before its deadline.
+    ///
+    /// Defaults to zero, which always arms the timer.
+    pub fn min_arm_duration(&mut self, duration: Duration) -> &mut Self {
+        self.min_arm_duration = duration;
+        self
+    }
+
+    /// Create a `Delay` that elapses at `deadline` using these settings.
+    pub fn build(&self, deadline: Instant) -> Result<Delay, IoError> {
+        Ok(Delay {
+            min_arm_duration: self.min_arm_duration,
+            ..Delay::new(deadline)?
+        })
+    }
 }
 
 impl Delay {
     /// Create a new `Delay` instance that elapses at `deadline`.
+    ///
+    /// The deadline is rounded up to the global timer slack, see
+    /// [`set_global_timer_slack`].
+    ///
+    /// [`set_global_timer_slack`]: fn.set_global_timer_slack.html
     pub fn new(deadline: Instant) -> Result<Self, IoError> {
+        let deadline = round_up_to_slack(deadline, global_timer_slack());
+        Delay::new_exact(deadline)
+    }
+
+    /// Creates a `Delay` at exactly `deadline`, ignoring the global slack.
+    fn new_exact(deadline: Instant) -> Result<Self, IoError> {
         let timerfd = TimerFd::new(ClockId::Monotonic)?;
         Ok(Delay {
-            timerfd,
+            timerfd: Some(timerfd),
+            ..Delay::unarmed(deadline)
+        })
+    }
+
+    /// Create a new `Delay` instance that elapses at `deadline`, driven by the
+    /// reactor behind `handle`.
+    ///
+    /// By default a timer registers with the reactor of the thread that first
+    /// polls it. With an explicit handle the `Delay` can be created and
+    /// awaited on any thread, e.g. a blocking worker outside the runtime,
+    /// while the reactor keeps running elsewhere.
+    ///
+    /// As with `Delay::new`, the deadline is rounded up to the global timer
+    /// slack.
+    pub fn new_with_handle(deadline: Instant, handle: &Handle) -> Result<Self, IoError> {
+        let timerfd = TimerFd::new_with_handle(ClockId::Monotonic, handle)?;
+        Ok(Delay {
+            timerfd: Some(timerfd),
+            ..Delay::lazy(deadline)
+        })
+    }
+
+    /// Create a new `Delay` instance that elapses at `deadline` and never
+    /// completes before `Instant::now()` has reached it.
+    ///
+    /// The monotonic clock shouldn't let a timer fire early, but clocks of
+    /// virtualized systems may jump. A strict `Delay` checks the clock once
+    /// its timer fires and re-arms for any shortfall, so the full duration is
+    /// guaranteed to have passed when it completes.
+    pub fn strict(deadline: Instant) -> Result<Self, IoError> {
+        Ok(Delay {
+            strict: true,
+            ..Delay::new(deadline)?
+        })
+    }
+
+    /// Create a new `Delay` instance that elapses at `deadline`, along with a
+    /// handle that resets it.
+    ///
+    /// The handle can be moved to another task, which then controls the
+    /// deadline while the `Delay` is awaited elsewhere. A reset through the
+    /// handle wakes the task awaiting the `Delay`.
+    pub fn with_reset_handle(deadline: Instant) -> Result<(Self, DelayResetHandle), IoError> {
+        let remote_reset = Arc::new(RemoteReset {
+            deadline: Mutex::new(None),
+            task: AtomicTask::new(),
+        });
+        let handle = DelayResetHandle {
+            shared: remote_reset.clone(),
+        };
+        let delay = Delay {
+            remote_reset: Some(remote_reset),
+            ..Delay::new(deadline)?
+        };
+        Ok((delay, handle))
+    }
+
+    /// Create a new `Delay` instance that is armed `compensation` before
+    /// `deadline`.
+    ///
+    /// Wakeups land some time after the timer expires. If that latency is
+    /// known, arming early by the same amount makes the `Delay` complete
+    /// closer to `deadline`, at the risk of completing before it. A
+    /// compensation longer than the time left completes the `Delay` on
+    /// first poll.
+    pub fn new_compensated(deadline: Instant, compensation: Duration) -> Result<Self, IoError> {
+        Ok(Delay {
+            compensation,
+            ..Delay::new(deadline)?
+        })
+    }
+
+    /// Create a new `Delay` instance that effectively never elapses.
+    ///
+    /// The deadline is the latest instant the kernel can represent for
+    /// `CLOCK_MONOTONIC`, roughly 292 years after boot. This is useful as a
+    /// placeholder branch in `select` loops.
+    pub fn far_future() -> Result<Self, IoError> {
+        Delay::new_exact(far_future_deadline())
+    }
+
+    /// Create a new `Delay` instance that elapses `extra` after the deadline
+    /// of `other`.
+    ///
+    /// The new deadline is relative to when `other` is scheduled to elapse,
+    /// not when it actually completes. It is clamped to the deadline of
+    /// `Delay::far_future` rather than overflowing.
+    pub fn after_delay(other: &Delay, extra: Duration) -> Result<Self, IoError> {
+        let far = far_future_deadline();
+        let deadline = other
+            .deadline
+            .checked_add(extra)
+            .map_or(far, |deadline| deadline.min(far));
+        Delay::new(deadline)
+    }
+
+    /// Returns a future that waits for `duration` and resolves with how long
+    /// after the deadline it actually completed.
+    ///
+    /// The timer is armed on first poll and the global slack is ignored, so
+    /// the overshoot measures the wakeup latency of the system. Averaging a
+    /// few runs gives an estimate to pass to `Delay::new_compensated`.
+    pub fn calibrate(duration: Duration) -> impl Future<Item = Duration, Error = IoError> {
+        future::lazy(move || {
+            let start = Instant::now();
+            Delay::new_exact(start + duration)
+                .map(move |delay| delay.map(move |()| start.elapsed().saturating_sub(duration)))
+        })
+        .flatten()
+    }
+
+    /// Create a new `Delay` instance that elapses at the later of `a` and
+    /// `b`, i.e. not before both have passed.
+    ///
+    /// The deadline is clamped to the deadline of `Delay::far_future`.
+    pub fn new_max(a: Instant, b: Instant) -> Result<Self, IoError> {
+        Delay::new(a.max(b).min(far_future_deadline()))
+    }
+
+    /// Create a new `Delay` instance that elapses at the earlier of `a` and
+    /// `b`.
+    ///
+    /// The deadline is clamped to the deadline of `Delay::far_future`.
+    pub fn new_min(a: Instant, b: Instant) -> Result<Self, IoError> {
+        Delay::new(a.min(b).min(far_future_deadline()))
+    }
+
+    /// Create a new `Delay` instance that elapses at `deadline` without
+    /// allocating a timer.
+    ///
+    /// The underlying timerfd is created on the first call to `poll`, so a
+    /// lazy `Delay` that is never polled costs no file descriptor. Errors
+    /// creating the timer are reported from `poll`.
+    ///
+    /// As with `Delay::new`, the deadline is rounded up to the global timer
+    /// slack.
+    pub fn lazy(deadline: Instant) -> Self {
+        Delay::unarmed(round_up_to_slack(deadline, global_timer_slack()))
+    }
+
+    /// Creates a `Delay` at exactly `deadline` without a timer.
+    fn unarmed(deadline: Instant) -> Self {
+        Delay {
+            timerfd: None,
             deadline,
+            wall_deadline: None,
+            min_arm_duration: Duration::ZERO,
+            compensation: Duration::ZERO,
+            yielded: false,
             initialized: false,
             task: None,
+            source: None,
+            watchdog: None,
+            strict: false,
+            remote_reset: None,
+        }
+    }
+
+    /// Create a new `Delay` instance that elapses once `clock` reaches
+    /// `deadline`.
+    ///
+    /// Waiting is delegated to the clock, which lets tests drive the `Delay`
+    /// with a `TestClock` instead of sleeping. No timerfd is created unless
+    /// the clock itself creates one.
+    pub fn new_with_clock_source(clock: Arc<dyn Clock>, deadline: Instant) -> Self {
+        let delay = clock.delay(deadline);
+        Delay {
+            source: Some((clock, delay)),
+            ..Delay::unarmed(deadline)
+        }
+    }
+
+    /// Create a new `Delay` instance that elapses at `deadline` and recovers
+    /// from a lost reactor wakeup.
+    ///
+    /// If the `Delay` is polled more than `grace` after its deadline and the
+    /// reactor still hasn't reported the timer as ready, the timerfd is read
+    /// directly. The `Delay` completes if the timer did expire, and the lost
+    /// wakeup is logged as a warning.
+    pub fn with_watchdog(deadline: Instant, grace: Duration) -> Result<Self, IoError> {
+        Ok(Delay {
+            watchdog: Some(grace),
+            ..Delay::new(deadline)?
         })
     }
 
+    /// Create a new `Delay` honoring an HTTP `Retry-After` value.
+    ///
+    /// A delay in seconds elapses that long from now. An HTTP date is armed
+    /// as an absolute `CLOCK_REALTIME` deadline, so the `Delay` elapses at
+    /// that wall-clock time even if the system clock is adjusted meanwhile.
+    pub fn from_retry_after(value: RetryAfter) -> Result<Self, IoError> {
+        match value {
+            RetryAfter::Delay(duration) => {
+                // The value comes from a peer, so an unrepresentable delay is
+                // clamped to the far future rather than overflowing.
+                let far = far_future_deadline();
+                let deadline = Instant::now()
+                    .checked_add(duration)
+                    .map_or(far, |d| d.min(far));
+                Delay::new(deadline)
+            }
+            RetryAfter::Date(date) => {
+                let timerfd = TimerFd::new(ClockId::Realtime)?;
+                Ok(Delay {
+                    timerfd: Some(timerfd),
+                    wall_deadline: Some(date),
+                    ..Delay::unarmed(instant_from_system_time(date))
+                })
+            }
+        }
+    }
+
+    /// Create a future that completes at `deadline` or as soon as `flag` is
+    /// set, whichever happens first.
+    ///
+    /// The flag is checked on every poll. To wake a pending poll promptly,
+    /// set the flag through a [`FlagNotifier`] obtained from
+    /// `OrFlag::notifier`.
+    ///
+    /// [`FlagNotifier`]: struct.FlagNotifier.html
+    pub fn or_flag(deadline: Instant, flag: Arc<AtomicBool>) -> Result<OrFlag, IoError> {
+        Ok(OrFlag {
+            delay: Delay::new(deadline)?,
+            flag,
+            task: Arc::new(AtomicTask::new()),
+        })
+    }
+
+    /// Turn this `Delay` into a future which can be cloned and awaited by
+    /// several tasks at once.
+    ///
+    /// All clones share the single underlying timer and complete together.
+    /// Errors are converted into the cloneable `TimerError`.
+    pub fn shared(self) -> SharedDelay {
+        self.map_err(TimerError::from as fn(IoError) -> TimerError)
+            .shared()
+    }
+
     /// Returns the instant at which the future will complete.
     pub fn deadline(&self) -> Instant {
         self.deadline
@@ -39,11 +357,202 @@ impl Delay {
     /// Reset the `Delay` instance to a new deadline.
     pub fn reset(&mut self, deadline: Instant) {
         self.deadline = deadline;
+        self.wall_deadline = None;
+        self.yielded = false;
         self.initialized = false;
+        if let Some((clock, delay)) = &mut self.source {
+            *delay = clock.delay(deadline);
+        }
         if let Some(task) = &self.task {
             task.notify()
         }
     }
+
+    /// Arms the timer and registers it with the reactor of the current
+    /// thread, or the fallback reactor outside of a runtime, ahead of the
+    /// first poll.
+    ///
+    /// This doesn't need to run inside a task, so latency-critical code can
+    /// do the setup work up front and the first poll only checks whether
+    /// the deadline passed. A `Delay` too close to its deadline to be armed,
+    /// one driven by a `Clock` or one already armed is left as is.
+    pub fn prime(&mut self) -> Result<(), IoError> {
+        if self.source.is_some() || self.initialized {
+            return Ok(());
+        }
+        let remaining = self.remaining();
+        if remaining.is_zero() || remaining < self.min_arm_duration {
+            return Ok(());
+        }
+        self.arm(remaining)?;
+        self.timerfd()?.register()
+    }
+
+    /// Block the current thread until the `Delay` has elapsed.
+    ///
+    /// This waits in a blocking `read` on a timerfd rather than going through
+    /// the reactor, so it works from plain threads without a running
+    /// executor. It must not be called from within an async task, as it
+    /// blocks the executor thread until the deadline.
+    pub fn block_until_elapsed(self) -> Result<(), IoError> {
+        let remaining = self.remaining();
+        if remaining.is_zero() {
+            return Ok(());
+        }
+        let clock = match self.wall_deadline {
+            Some(_) => ClockId::Realtime,
+            None => ClockId::Monotonic,
+        };
+        let (state, flags) = self.timer_state(remaining);
+        // A blocking timerfd makes the read wait for the expiration.
+        let mut timerfd = Inner(create(clock, false)?);
+        timerfd.set_state(state, flags);
+        timerfd.read().map(drop)
+    }
+
+    /// Returns the time left until the timer should expire.
+    fn remaining(&self) -> Duration {
+        // Saturate so a clock observed to step past the deadline can't
+        // produce a negative duration.
+        let remaining = match self.wall_deadline {
+            Some(wall) => wall
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO),
+            None => self.deadline.saturating_duration_since(Instant::now()),
+        };
+        remaining.saturating_sub(self.compensation)
+    }
+
+    /// Returns the timer setting that fires at the deadline, `remaining`
+    /// from now.
+    fn timer_state(&self, remaining: Duration) -> (TimerState, SetTimeFlags) {
+        match self.wall_deadline {
+            Some(wall) => {
+                let since_epoch = wall.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+                let expiry = since_epoch.saturating_sub(self.compensation);
+                (TimerState::Oneshot(expiry), SetTimeFlags::Abstime)
+            }
+            None => (TimerState::Oneshot(remaining), SetTimeFlags::Default),
+        }
+    }
+
+    /// Polls the underlying timer, without the strict deadline check.
+    fn poll_timer(&mut self) -> Result<Async<()>, IoError> {
+        if let Some((_, delay)) = &mut self.source {
+            return delay.poll();
+        }
+        if !self.initialized {
+            let duration = self.remaining();
+            if duration.is_zero() || self.yielded {
+                return Ok(Async::Ready(()));
+            }
+            if duration < self.min_arm_duration {
+                self.yielded = true;
+                task::current().notify();
+                return Ok(Async::NotReady);
+            }
+            self.arm(duration)?;
+        }
+        if self.timerfd()?.poll_read()?.is_ready() {
+            return Ok(Async::Ready(()));
+        }
+        match self.watchdog {
+            Some(grace) if Instant::now() >= watchdog_deadline(self.deadline, grace) => {
+                if self.timerfd()?.read_now()? == 0 {
+                    return Ok(Async::NotReady);
+                }
+                warn!(
+                    "timerfd expired without a reactor wakeup, recovered {:?} after the deadline",
+                    self.deadline.elapsed()
+                );
+                Ok(Async::Ready(()))
+            }
+            _ => Ok(Async::NotReady),
+        }
+    }
+
+    fn arm(&mut self, remaining: Duration) -> Result<(), IoError> {
+        let (state, flags) = self.timer_state(remaining);
+        let timerfd = self.timerfd()?;
+        match flags {
+            SetTimeFlags::Abstime => timerfd.set_abstime(state)?,
+            flags => {
+                timerfd.set_state(state, flags);
+            }
+        }
+        self.initialized = true;
+        Ok(())
+    }
+
+    fn timerfd(&mut self) -> Result<&mut TimerFd, IoError> {
+        if self.timerfd.is_none() {
+            let clock = match self.wall_deadline {
+                Some(_) => ClockId::Realtime,
+                None => ClockId::Monotonic,
+            };
+            self.timerfd = Some(TimerFd::new(clock)?);
+        }
+        Ok(self.timerfd.as_mut().unwrap())
+    }
+}
+
+/// Process-wide timer slack in nanoseconds, zero if disabled.
+static GLOBAL_SLACK: AtomicU64 = AtomicU64::new(0);
+
+/// Set the process-wide timer slack.
+///
+/// Deadlines of delays created by `Delay::new`, `Delay::lazy`,
+/// `Delay::new_with_handle` and the constructors and functions built on
+/// them, such as `sleep`, are rounded up to the next multiple of `slack`,
+/// counted from a fixed instant shared by the whole process. Timers with
+/// nearby deadlines then expire together, which reduces the number of
+/// wakeups at the cost of up to `slack` of added latency. A slack of zero,
+/// the default, disables rounding. Hard limits, such as the cap of a
+/// `CappedDelay`, are never rounded.
+pub fn set_global_timer_slack(slack: Duration) {
+    let nanos = u64::try_from(slack.as_nanos()).unwrap_or(u64::MAX);
+    GLOBAL_SLACK.store(nanos, Ordering::Relaxed);
+}
+
+/// Returns the process-wide timer slack set by `set_global_timer_slack`.
+pub fn global_timer_slack() -> Duration {
+    Duration::from_nanos(GLOBAL_SLACK.load(Ordering::Relaxed))
+}
+
+/// Rounds `deadline` up to the next multiple of `slack` after a fixed
+/// process-wide origin.
+fn round_up_to_slack(deadline: Instant, slack: Duration) -> Instant {
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    if slack.is_zero() {
+        return deadline;
+    }
+    let origin = *ORIGIN.get_or_init(Instant::now);
+    let since = match deadline.checked_duration_since(origin) {
+        Some(since) => since.as_nanos(),
+        None => return deadline,
+    };
+    let slack = slack.as_nanos();
+    let rounded = origin + duration_from_nanos(since.div_ceil(slack) * slack);
+    // Never push a deadline past what the kernel can arm.
+    rounded.min(far_future_deadline()).max(deadline)
+}
+
+/// Returns `deadline + grace`, the instant after which the watchdog reads
+/// the timerfd directly, or the far-future deadline if the sum overflows.
+fn watchdog_deadline(deadline: Instant, grace: Duration) -> Instant {
+    deadline
+        .checked_add(grace)
+        .unwrap_or_else(far_future_deadline)
+}
+
+/// Returns the latest instant a `CLOCK_MONOTONIC` timer can be armed for.
+fn far_future_deadline() -> Instant {
+    // The kernel stores timer expiry as signed 64-bit nanoseconds.
+    let max = Duration::from_nanos(i64::MAX as u64);
+    let now = Instant::now();
+    let uptime = rustix::time::clock_gettime(rustix::time::ClockId::Monotonic);
+    let uptime = Duration::new(uptime.tv_sec as u64, uptime.tv_nsec as u32);
+    now + max.saturating_sub(uptime)
 }
 
 impl Future for Delay {
@@ -51,35 +560,178 @@ impl Future for Delay {
     type Error = IoError;
 
     fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
-        if !self.initialized {
-            let now = Instant::now();
-            let duration = if self.deadline > now {
-                self.deadline - now
-            } else {
+        if let Some(remote_reset) = &self.remote_reset {
+            // Register before taking the deadline so a concurrent reset can't
+            // be missed.
+            remote_reset.task.register();
+            let deadline = remote_reset.deadline.lock().unwrap().take();
+            if let Some(deadline) = deadline {
+                self.reset(deadline);
+            }
+        }
+        // Remember the task so that `reset` can wake it to re-arm the timer.
+        match &self.task {
+            Some(task) if task.will_notify_current() => {}
+            _ => self.task = Some(task::current()),
+        }
+        loop {
+            try_ready!(self.poll_timer());
+            if !self.strict || Instant::now() >= self.deadline {
                 return Ok(Async::Ready(()));
-            };
-            self.timerfd
-                .set_state(TimerState::Oneshot(duration), SetTimeFlags::Default);
-            self.initialized = true;
+            }
+            // The timer fired before the clock reached the deadline, re-arm
+            // for the shortfall.
+            self.yielded = false;
+            self.initialized = false;
+        }
+    }
+}
+
+struct RemoteReset {
+    deadline: Mutex<Option<Instant>>,
+    task: AtomicTask,
+}
+
+/// Resets a `Delay` from another task.
+///
+/// Created by `Delay::with_reset_handle`.
+#[derive(Clone)]
+pub struct DelayResetHandle {
+    shared: Arc<RemoteReset>,
+}
+
+impl DelayResetHandle {
+    /// Reset the paired `Delay` to a new deadline.
+    ///
+    /// The new deadline takes effect the next time the `Delay` is polled; the
+    /// task awaiting it is woken to make that happen right away.
+    pub fn reset(&self, deadline: Instant) {
+        *self.shared.deadline.lock().unwrap() = Some(deadline);
+        self.shared.task.notify();
+    }
+
+    /// Reset the paired `Delay` to elapse `duration` from now.
+    pub fn reset_after(&self, duration: Duration) {
+        self.reset(Instant::now() + duration);
+    }
+}
+
+/// A future that completes at a deadline or once a flag is set.
+///
+/// Created by `Delay::or_flag`. Resolves to `true` if the flag was set and
+/// `false` if the deadline was reached.
+pub struct OrFlag {
+    delay: Delay,
+    flag: Arc<AtomicBool>,
+    task: Arc<AtomicTask>,
+}
+
+impl OrFlag {
+    /// Returns a handle that sets the flag and wakes the task polling this
+    /// future.
+    pub fn notifier(&self) -> FlagNotifier {
+        FlagNotifier {
+            flag: self.flag.clone(),
+            task: self.task.clone(),
         }
-        try_ready!(self.timerfd.poll_read());
-        Ok(Async::Ready(()))
+    }
+}
+
+impl Future for OrFlag {
+    type Item = bool;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        // Register before checking the flag so a concurrent `set` can't be
+        // missed.
+        self.task.register();
+        if self.flag.load(Ordering::SeqCst) {
+            return Ok(Async::Ready(true));
+        }
+        try_ready!(self.delay.poll());
+        Ok(Async::Ready(false))
+    }
+}
+
+/// Sets the flag of an `OrFlag` future, waking it up.
+#[derive(Clone)]
+pub struct FlagNotifier {
+    flag: Arc<AtomicBool>,
+    task: Arc<AtomicTask>,
+}
+
+impl FlagNotifier {
+    /// Set the flag and wake the task awaiting the paired future.
+    pub fn set(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        self.task.notify();
+    }
+}
+
+/// A `Delay` that can be pushed back, but never past a hard cap.
+///
+/// Useful for timeouts that are extended on activity, where a peer must not
+/// be able to keep the timeout from ever firing.
+pub struct CappedDelay {
+    delay: Delay,
+    cap: Instant,
+}
+
+impl CappedDelay {
+    /// Create a new `CappedDelay` that elapses at `initial`, or at `cap` if
+    /// that is earlier.
+    pub fn new(initial: Instant, cap: Instant) -> Result<CappedDelay, IoError> {
+        Ok(CappedDelay {
+            // Rounding to the global slack could push the deadline past the cap.
+            delay: Delay::new_exact(initial.min(cap))?,
+            cap,
+        })
+    }
+
+    /// Push the deadline back by `by`, up to the cap.
+    ///
+    /// Returns `true` if the extension was applied in full and `false` if the
+    /// deadline was clamped to the cap.
+    pub fn extend(&mut self, by: Duration) -> bool {
+        let wanted = self.delay.deadline.checked_add(by);
+        let deadline = wanted.map_or(self.cap, |wanted| wanted.min(self.cap));
+        self.delay.reset(deadline);
+        wanted == Some(deadline)
+    }
+
+    /// Returns the instant at which the future will complete.
+    pub fn deadline(&self) -> Instant {
+        self.delay.deadline
+    }
+
+    /// Returns the latest instant the deadline can be extended to.
+    pub fn cap(&self) -> Instant {
+        self.cap
+    }
+}
+
+impl Future for CappedDelay {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        self.delay.poll()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::{Duration, Instant};
-    use tokio::prelude::*;
+    use std::thread;
+    use tokio::runtime::current_thread;
 
     #[test]
     fn delay_zero_duration() {
         tokio::run(future::lazy(|| {
             let now = Instant::now();
-            let delay = Delay::new(Instant::now());
+            let delay = Delay::new(Instant::now()).unwrap();
             delay
-                .and_then(|_| {
+                .and_then(move |_| {
                     let elapsed = now.elapsed();
                     println!("{:?}", elapsed);
                     assert!(elapsed < Duration::from_millis(1));
@@ -93,19 +745,19 @@ mod tests {
     fn dropped_delay_doesnt_fire() {
         tokio::run(future::lazy(|| {
             let now = Instant::now();
-            let mut delay_fired = false;
+            // The flag is shared, a captured `bool` would be copied into the
+            // first closure.
+            let delay_fired = Arc::new(AtomicBool::new(false));
+            let fired = delay_fired.clone();
             let delay = Delay::new(now + Duration::from_millis(500))
                 .unwrap()
                 .and_then(move |_| {
-                    delay_fired = true;
+                    fired.store(true, Ordering::SeqCst);
                     Ok(())
                 });
             delay
                 .select(future::ok(()))
-                .and_then(move |_| {
-                    assert_eq!(delay_fired, false);
-                    Ok(())
-                })
+                .map(move |_| assert!(!delay_fired.load(Ordering::SeqCst)))
                 .map_err(|_err| panic!())
         }))
     }
@@ -114,9 +766,9 @@ mod tests {
     fn delay_works() {
         tokio::run(future::lazy(|| {
             let now = Instant::now();
-            let delay = Delay::new(now + Duration::from_micros(10));
+            let delay = Delay::new(now + Duration::from_micros(10)).unwrap();
             delay
-                .and_then(|_| {
+                .and_then(move |_| {
                     let elapsed = now.elapsed();
                     println!("{:?}", elapsed);
                     assert!(elapsed < Duration::from_millis(1));
@@ -125,4 +777,517 @@ mod tests {
                 .map_err(|err| panic!("{:?}", err))
         }));
     }
+
+    #[test]
+    fn lazy_delay_allocates_on_poll() {
+        crate::syscalls::take();
+        let now = Instant::now();
+        let delays: Vec<_> = (0..10_000)
+            .map(|i| Delay::lazy(now + Duration::from_millis(i)))
+            .collect();
+        assert!(delays.iter().all(|delay| delay.timerfd.is_none()));
+
+        let delay = Delay::lazy(Instant::now() + Duration::from_millis(10));
+        assert_eq!(crate::syscalls::take().create, 0);
+        current_thread::block_on_all(delay).unwrap();
+        assert_eq!(crate::syscalls::take().create, 1);
+    }
+
+    #[test]
+    fn delay_stays_within_syscall_budget() {
+        crate::syscalls::take();
+        let delay = Delay::new(Instant::now() + Duration::from_millis(1)).unwrap();
+        current_thread::block_on_all(delay).unwrap();
+        let counts = crate::syscalls::take();
+        assert_eq!(
+            counts,
+            crate::syscalls::Counts {
+                create: 1,
+                settime: 1,
+                gettime: 0,
+                read: 1,
+                epoll_ctl: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn or_flag_fires_on_deadline() {
+        let handle = thread::spawn(|| {
+            let now = Instant::now();
+            let flag = Arc::new(AtomicBool::new(false));
+            let delay = Delay::or_flag(now + Duration::from_millis(10), flag).unwrap();
+            let by_flag = current_thread::block_on_all(delay).unwrap();
+            (by_flag, now.elapsed())
+        });
+        let (by_flag, elapsed) = handle.join().unwrap();
+        assert!(!by_flag);
+        assert!(elapsed >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn or_flag_fires_on_flag() {
+        let now = Instant::now();
+        let flag = Arc::new(AtomicBool::new(false));
+        let delay = Delay::or_flag(now + Duration::from_secs(10), flag).unwrap();
+        let notifier = delay.notifier();
+        let setter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            notifier.set();
+        });
+        let by_flag = current_thread::block_on_all(delay).unwrap();
+        setter.join().unwrap();
+        assert!(by_flag);
+        assert!(now.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn delay_deadline_at_boundary() {
+        for nanos in 0..100 {
+            let deadline = Instant::now() + Duration::from_nanos(nanos);
+            let delay = Delay::new(deadline).unwrap();
+            current_thread::block_on_all(delay).unwrap();
+            assert!(Instant::now() >= deadline);
+        }
+    }
+
+    #[test]
+    fn shared_delay_completes_all_clones() {
+        crate::syscalls::take();
+        let now = Instant::now();
+        let deadline = now + Duration::from_millis(10);
+        let shared = Delay::new(deadline).unwrap().shared();
+        let waiters = (0..3).map(|_| shared.clone().map(|_| Instant::now()));
+        let fired = current_thread::block_on_all(future::join_all(waiters)).unwrap();
+        assert_eq!(crate::syscalls::take().create, 1);
+        assert_eq!(fired.len(), 3);
+        for instant in fired {
+            assert!(instant >= deadline);
+            assert!(instant - deadline < Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn short_delay_yields_instead_of_arming() {
+        let mut delay = DelayBuilder::new()
+            .min_arm_duration(Duration::from_millis(10))
+            .build(Instant::now() + Duration::from_millis(1))
+            .unwrap();
+        crate::syscalls::take();
+        let polls = current_thread::block_on_all(future::lazy(move || {
+            let first = delay.poll().unwrap();
+            let second = delay.poll().unwrap();
+            Ok::<_, ()>((first, second))
+        }))
+        .unwrap();
+        assert_eq!(polls, (Async::NotReady, Async::Ready(())));
+        assert_eq!(crate::syscalls::take().settime, 0);
+    }
+
+    #[test]
+    fn far_future_delay_does_not_fire() {
+        let now = Instant::now();
+        let mut delay = Delay::far_future().unwrap();
+        assert!(delay.deadline > now + Duration::from_secs(100 * 365 * 24 * 3600));
+        let mut rt = current_thread::Runtime::new().unwrap();
+        assert!(rt
+            .block_on(future::lazy(|| delay.poll()))
+            .unwrap()
+            .is_not_ready());
+        match delay.timerfd.as_ref().unwrap().get_state() {
+            TimerState::Oneshot(_) => {}
+            state => panic!("unexpected state: {:?}", state),
+        }
+        let short = Delay::new(now + Duration::from_millis(20)).unwrap();
+        match rt.block_on(delay.select2(short)) {
+            Ok(future::Either::B(_)) => {}
+            _ => panic!("far future delay fired"),
+        }
+    }
+
+    #[test]
+    fn retry_after_delay_seconds() {
+        let now = Instant::now();
+        let delay = Delay::from_retry_after(RetryAfter::Delay(Duration::from_millis(20))).unwrap();
+        current_thread::block_on_all(delay).unwrap();
+        assert!(now.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn retry_after_huge_delay_is_clamped() {
+        let delay = Delay::from_retry_after(RetryAfter::Delay(Duration::from_secs(u64::MAX)));
+        let far = Delay::far_future().unwrap();
+        // Both deadlines are the kernel limit, up to sampling jitter.
+        let jitter = Duration::from_millis(1);
+        let deadline = delay.unwrap().deadline;
+        assert!(deadline < far.deadline + jitter);
+        assert!(deadline + jitter > far.deadline);
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let date = SystemTime::now() + Duration::from_millis(20);
+        let delay = Delay::from_retry_after(RetryAfter::Date(date)).unwrap();
+        current_thread::block_on_all(delay).unwrap();
+        assert!(SystemTime::now() >= date);
+    }
+
+    #[test]
+    fn block_until_elapsed_from_plain_thread() {
+        let elapsed = thread::spawn(|| {
+            let now = Instant::now();
+            let delay = Delay::lazy(now + Duration::from_millis(20));
+            delay.block_until_elapsed().unwrap();
+            now.elapsed()
+        })
+        .join()
+        .unwrap();
+        assert!(elapsed >= Duration::from_millis(20), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(200), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn watchdog_recovers_missed_wakeup() {
+        let result = current_thread::block_on_all(future::lazy(|| {
+            let now = Instant::now();
+            let mut delay =
+                Delay::with_watchdog(now + Duration::from_millis(5), Duration::from_millis(5))
+                    .unwrap();
+            assert!(delay.poll().unwrap().is_not_ready());
+            // Blocking the task keeps the reactor from delivering the wakeup.
+            thread::sleep(Duration::from_millis(20));
+            Ok::<_, ()>(delay.poll().unwrap())
+        }));
+        assert!(result.unwrap().is_ready());
+    }
+
+    #[test]
+    fn watchdog_with_unbounded_grace_never_triggers() {
+        let result = current_thread::block_on_all(future::lazy(|| {
+            let now = Instant::now();
+            let mut delay =
+                Delay::with_watchdog(now + Duration::from_millis(5), Duration::MAX).unwrap();
+            assert!(delay.poll().unwrap().is_not_ready());
+            thread::sleep(Duration::from_millis(20));
+            Ok::<_, ()>(delay.poll().unwrap())
+        }));
+        // No wakeup was delivered and the grace period never ends.
+        assert!(result.unwrap().is_not_ready());
+    }
+
+    #[test]
+    fn after_delay_is_relative_to_deadline() {
+        let first = Delay::new(Instant::now() + Duration::from_millis(50)).unwrap();
+        let second = Delay::after_delay(&first, Duration::from_millis(20)).unwrap();
+        assert_eq!(second.deadline, first.deadline + Duration::from_millis(20));
+
+        let far = Delay::far_future().unwrap();
+        let clamped = Delay::after_delay(&far, Duration::from_secs(u64::MAX)).unwrap();
+        // Both deadlines are the kernel limit, up to sampling jitter.
+        let jitter = Duration::from_millis(1);
+        assert!(clamped.deadline < far.deadline + jitter);
+        assert!(clamped.deadline + jitter > far.deadline);
+    }
+
+    #[test]
+    fn delay_driven_by_std_waker_without_runtime() {
+        use futures::executor::{self, Notify};
+        use std::task::{Wake, Waker};
+
+        struct ThreadWake {
+            thread: thread::Thread,
+            woken: AtomicBool,
+        }
+
+        impl Wake for ThreadWake {
+            fn wake(self: Arc<Self>) {
+                self.woken.store(true, Ordering::SeqCst);
+                self.thread.unpark();
+            }
+        }
+
+        // Delay is a futures 0.1 future, so executors built on `std::task`
+        // bridge their `Waker` through `Notify`.
+        struct WakerNotify(Waker);
+
+        impl Notify for WakerNotify {
+            fn notify(&self, _id: usize) {
+                self.0.wake_by_ref();
+            }
+        }
+
+        let wake = Arc::new(ThreadWake {
+            thread: thread::current(),
+            woken: AtomicBool::new(false),
+        });
+        let notify = Arc::new(WakerNotify(Waker::from(wake.clone())));
+        let now = Instant::now();
+        let mut delay = executor::spawn(Delay::new(now + Duration::from_millis(20)).unwrap());
+        let mut polls = 0;
+        while delay.poll_future_notify(&notify, 0).unwrap().is_not_ready() {
+            polls += 1;
+            while !wake.woken.swap(false, Ordering::SeqCst) {
+                thread::park_timeout(Duration::from_secs(1));
+                assert!(now.elapsed() < Duration::from_secs(1), "never woken");
+            }
+        }
+        assert!(polls >= 1);
+        assert!(now.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn past_http_date_fires_promptly() {
+        let now = Instant::now();
+        let date = SystemTime::now() - Duration::from_millis(5);
+        let delay = Delay::from_retry_after(RetryAfter::Date(date)).unwrap();
+        current_thread::block_on_all(delay).unwrap();
+        assert!(now.elapsed() < Duration::from_millis(5));
+    }
+
+    #[test]
+    fn capped_delay_clamps_extensions() {
+        let now = Instant::now();
+        let cap = now + Duration::from_millis(100);
+        let mut delay = CappedDelay::new(now + Duration::from_millis(20), cap).unwrap();
+        assert!(delay.extend(Duration::from_millis(30)));
+        assert_eq!(delay.delay.deadline, now + Duration::from_millis(50));
+        assert!(!delay.extend(Duration::from_millis(80)));
+        assert_eq!(delay.delay.deadline, cap);
+        assert!(!delay.extend(Duration::from_secs(u64::MAX)));
+        assert_eq!(delay.delay.deadline, cap);
+
+        current_thread::block_on_all(delay).unwrap();
+        assert!(Instant::now() >= cap);
+    }
+
+    #[test]
+    fn delay_with_handle_completes_on_other_thread() {
+        let reactor = tokio_reactor::Reactor::new().unwrap().background().unwrap();
+        let handle = reactor.handle().clone();
+        let elapsed = thread::spawn(move || {
+            let now = Instant::now();
+            let delay = Delay::new_with_handle(now + Duration::from_millis(20), &handle).unwrap();
+            delay.wait().unwrap();
+            now.elapsed()
+        })
+        .join()
+        .unwrap();
+        assert!(elapsed >= Duration::from_millis(20), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+        reactor.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn strict_delay_never_completes_early() {
+        for micros in (0..20).map(|n| 100 + n * 150) {
+            let requested = Duration::from_micros(micros);
+            let now = Instant::now();
+            let delay = Delay::strict(now + requested).unwrap();
+            current_thread::block_on_all(delay).unwrap();
+            let elapsed = now.elapsed();
+            assert!(elapsed >= requested, "{:?} < {:?}", elapsed, requested);
+        }
+    }
+
+    #[test]
+    fn reset_handle_moves_deadline_from_other_task() {
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let now = Instant::now();
+        let (delay, handle) = Delay::with_reset_handle(now + Duration::from_secs(5)).unwrap();
+        let resetter = Delay::new(now + Duration::from_millis(10))
+            .unwrap()
+            .map(move |_| handle.reset_after(Duration::from_millis(20)))
+            .map_err(|err| panic!("{:?}", err));
+        rt.spawn(resetter);
+        rt.block_on(delay).unwrap();
+        let elapsed = now.elapsed();
+        assert!(elapsed >= Duration::from_millis(30), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn compensated_delay_arms_early() {
+        let nominal = Duration::from_millis(50);
+        let compensation = Duration::from_millis(20);
+        let now = Instant::now();
+        let mut delay = Delay::new_compensated(now + nominal, compensation).unwrap();
+        let armed = current_thread::block_on_all(future::lazy(move || {
+            assert!(delay.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(delay.timerfd.as_ref().unwrap().get_state())
+        }))
+        .unwrap();
+        match armed {
+            TimerState::Oneshot(armed) => {
+                assert!(armed <= nominal - compensation, "{:?}", armed);
+                assert!(armed > nominal - compensation * 2, "{:?}", armed);
+            }
+            state => panic!("unexpected timer state: {:?}", state),
+        }
+
+        let mut early = Delay::new_compensated(now + nominal, nominal * 2).unwrap();
+        let poll = current_thread::block_on_all(future::lazy(move || early.poll())).unwrap();
+        assert!(poll.is_ready());
+    }
+
+    #[test]
+    fn slack_rounds_nearby_deadlines_together() {
+        let slack = Duration::from_millis(1);
+        let bucket = round_up_to_slack(Instant::now() + Duration::from_millis(5), slack);
+        let first = round_up_to_slack(bucket - Duration::from_micros(800), slack);
+        let second = round_up_to_slack(bucket - Duration::from_micros(700), slack);
+        assert_eq!(first, bucket);
+        assert_eq!(second, bucket);
+        assert_eq!(round_up_to_slack(bucket, slack), bucket);
+
+        let deadline = Instant::now() + Duration::from_micros(100);
+        assert_eq!(round_up_to_slack(deadline, Duration::ZERO), deadline);
+    }
+
+    #[test]
+    fn round_up_to_slack_picks_next_bucket() {
+        let slack = Duration::from_millis(1);
+        let deadline = Instant::now() + Duration::from_millis(5);
+        let bucket = round_up_to_slack(deadline, slack);
+        assert!(bucket >= deadline && bucket - deadline < slack);
+        // Deadlines in the same bucket round together, a bucket is kept.
+        let near = bucket - Duration::from_micros(300);
+        assert_eq!(round_up_to_slack(near, slack), bucket);
+        assert_eq!(round_up_to_slack(bucket, slack), bucket);
+        assert_eq!(round_up_to_slack(deadline, Duration::ZERO), deadline);
+        // Rounding stops at the kernel limit, up to sampling jitter.
+        let far = far_future_deadline();
+        let rounded = round_up_to_slack(far, Duration::from_secs(3600));
+        assert!(rounded < far + Duration::from_millis(1));
+    }
+
+    /// Serializes the tests changing the global timer slack and restores
+    /// the previous slack once dropped.
+    struct SlackGuard {
+        previous: Duration,
+        _lock: std::sync::MutexGuard<'static, ()>,
+    }
+
+    impl SlackGuard {
+        fn set(slack: Duration) -> SlackGuard {
+            static LOCK: Mutex<()> = Mutex::new(());
+            let lock = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+            let previous = global_timer_slack();
+            set_global_timer_slack(slack);
+            SlackGuard {
+                previous,
+                _lock: lock,
+            }
+        }
+    }
+
+    impl Drop for SlackGuard {
+        fn drop(&mut self) {
+            set_global_timer_slack(self.previous);
+        }
+    }
+
+    #[test]
+    fn global_slack_applies_to_standard_constructors() {
+        let slack = Duration::from_millis(1);
+        let guard = SlackGuard::set(slack);
+        assert_eq!(global_timer_slack(), slack);
+        let bucket = round_up_to_slack(Instant::now() + Duration::from_millis(5), slack);
+        let first = Delay::new(bucket - Duration::from_micros(800)).unwrap();
+        let second = Delay::lazy(bucket - Duration::from_micros(700));
+        let handle = Handle::default();
+        let third = Delay::new_with_handle(bucket - Duration::from_micros(600), &handle).unwrap();
+        let cap = bucket - Duration::from_micros(500);
+        let capped = CappedDelay::new(cap + Duration::from_millis(10), cap).unwrap();
+        drop(guard);
+
+        assert_eq!(first.deadline, bucket);
+        assert_eq!(second.deadline, bucket);
+        assert_eq!(third.deadline, bucket);
+        assert_eq!(capped.delay.deadline, cap);
+        let exact = Instant::now() + Duration::from_micros(100);
+        assert_eq!(Delay::lazy(exact).deadline, exact);
+    }
+
+    #[test]
+    fn reset_before_first_poll() {
+        let start = Instant::now();
+        let mut delay = Delay::new(start + Duration::from_secs(5)).unwrap();
+        delay.reset(start + Duration::from_millis(20));
+        current_thread::block_on_all(delay).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reset_while_pending_wakes_task() {
+        let start = Instant::now();
+        let mut delay = Delay::new(start + Duration::from_secs(5)).unwrap();
+        let mut reset = false;
+        // Reset the pending delay without polling it again, as a task
+        // selecting over several events would.
+        let task = future::poll_fn(move || {
+            if reset {
+                return delay.poll();
+            }
+            assert!(delay.poll()?.is_not_ready());
+            delay.reset(Instant::now() + Duration::from_millis(20));
+            reset = true;
+            Ok::<_, IoError>(Async::NotReady)
+        });
+        current_thread::block_on_all(task).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reset_after_firing() {
+        let mut delay = Delay::new(Instant::now() + Duration::from_millis(10)).unwrap();
+        let mut runtime = current_thread::Runtime::new().unwrap();
+        runtime.block_on(future::poll_fn(|| delay.poll())).unwrap();
+
+        let reset = Instant::now();
+        delay.reset(reset + Duration::from_millis(30));
+        assert_eq!(delay.deadline, reset + Duration::from_millis(30));
+        runtime.block_on(future::poll_fn(|| delay.poll())).unwrap();
+        assert!(reset.elapsed() >= Duration::from_millis(30));
+        assert!(reset.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn new_max_and_new_min_pick_deadline() {
+        let start = Instant::now();
+        let early = start + Duration::from_millis(10);
+        let late = start + Duration::from_millis(40);
+
+        current_thread::block_on_all(Delay::new_min(late, early).unwrap()).unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(10), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(40), "{:?}", elapsed);
+
+        current_thread::block_on_all(Delay::new_max(early, late).unwrap()).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        let far = Instant::now() + Duration::from_secs(1_000 * 365 * 86_400);
+        let clamped = Delay::new_max(start, far).unwrap();
+        assert!(clamped.deadline <= far_future_deadline());
+    }
+
+    #[test]
+    fn calibrate_measures_overshoot() {
+        let duration = Duration::from_millis(5);
+        let start = Instant::now();
+        let overshoot = current_thread::block_on_all(Delay::calibrate(duration)).unwrap();
+        assert!(start.elapsed() >= duration + overshoot);
+        assert!(overshoot < Duration::from_millis(20), "{:?}", overshoot);
+    }
+
+    #[test]
+    fn primed_delay_polls_without_arming() {
+        let mut delay = Delay::new(Instant::now() + Duration::from_millis(10)).unwrap();
+        delay.prime().unwrap();
+        crate::syscalls::take();
+        current_thread::block_on_all(future::poll_fn(|| delay.poll())).unwrap();
+        assert_eq!(crate::syscalls::take().settime, 0);
+        assert!(Instant::now() >= delay.deadline);
+    }
 }
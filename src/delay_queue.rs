@@ -31,7 +31,7 @@ impl Eq for Entry {}
 
 /// Token to a value stored in a `DelayQueue`.
 #[derive(Debug)]
-pub struct Key(usize);
+pub struct Key(());
 
 /// A queue of delayed elements.
 ///
@@ -41,6 +41,7 @@ pub struct DelayQueue<T> {
     timerfd: TimerFd,
     slab: Slab<T>,
     heap: BinaryHeap<Reverse<Entry>>,
+    armed: Option<Instant>,
     task: Option<task::Task>,
 }
 
@@ -54,6 +55,7 @@ impl<T> DelayQueue<T> {
             timerfd,
             heap: BinaryHeap::new(),
             slab: Slab::new(),
+            armed: None,
             task: None,
         })
     }
@@ -62,9 +64,14 @@ impl<T> DelayQueue<T> {
         let now = Instant::now();
         if let Some(item) = self.heap.peek() {
             if item.0.expiration > now {
-                let duration = item.0.expiration - now;
-                self.timerfd
-                    .set_state(TimerState::Oneshot(duration), SetTimeFlags::Default);
+                // Re-arming for the deadline the timer already waits for
+                // would only cost another syscall.
+                if self.armed != Some(item.0.expiration) {
+                    let duration = item.0.expiration - now;
+                    self.timerfd
+                        .set_state(TimerState::Oneshot(duration), SetTimeFlags::Default);
+                    self.armed = Some(item.0.expiration);
+                }
             } else {
                 let item = self.heap.pop().unwrap();
                 let data = self.slab.remove(item.0.index);
@@ -73,11 +80,7 @@ impl<T> DelayQueue<T> {
                     task.notify();
                 }
 
-                return Ok(Async::Ready(Some(Expired {
-                    data,
-                    deadline: item.0.expiration,
-                    key: Key(item.0.index),
-                })));
+                return Ok(Async::Ready(Some(Expired { data })));
             };
         }
         Ok(Async::NotReady)
@@ -101,7 +104,7 @@ impl<T> DelayQueue<T> {
         if let Some(task) = &self.task {
             task.notify();
         }
-        Key(idx)
+        Key(())
     }
 
     /// Insert `value` into the queue set to expire after the requested duration
@@ -118,6 +121,7 @@ impl<T> DelayQueue<T> {
         // TODO: should return None
         self.heap.clear();
         self.slab.clear();
+        self.armed = None;
     }
 }
 
@@ -125,8 +129,6 @@ impl<T> DelayQueue<T> {
 #[derive(Debug)]
 pub struct Expired<T> {
     data: T,
-    deadline: Instant,
-    key: Key,
 }
 
 impl<T> Expired<T> {
@@ -140,7 +142,9 @@ impl<T> Stream for DelayQueue<T> {
     type Error = IoError;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
-        self.timerfd.poll_read()?;
+        if self.timerfd.poll_read()?.is_ready() {
+            self.armed = None;
+        }
         self.task = Some(task::current());
         let expired = try_ready!(self.poll_next());
         Ok(Async::Ready(expired))
@@ -152,6 +156,7 @@ mod tests {
     use super::*;
     use std::time::{Duration, Instant};
     use tokio::prelude::*;
+    use tokio::runtime::current_thread;
 
     #[test]
     fn delay_queue_insert() {
@@ -194,4 +199,32 @@ mod tests {
                 .map_err(|_| ())
         }))
     }
+
+    #[test]
+    fn delay_queue_arms_once_per_deadline() {
+        let mut queue = DelayQueue::new().unwrap();
+        let now = Instant::now();
+        queue.insert_at(1u32, now + Duration::from_secs(60));
+        queue.insert_at(2u32, now + Duration::from_secs(120));
+        let polls = future::lazy(|| {
+            crate::syscalls::take();
+            for _ in 0..3 {
+                assert!(queue.poll().unwrap().is_not_ready());
+            }
+            let waiting = crate::syscalls::take();
+            // An earlier deadline is armed on the next poll.
+            queue.insert_at(3u32, now + Duration::from_secs(30));
+            assert!(queue.poll().unwrap().is_not_ready());
+            assert!(queue.poll().unwrap().is_not_ready());
+            Ok::<_, ()>((waiting, crate::syscalls::take()))
+        });
+        let (waiting, rearmed) = current_thread::block_on_all(polls).unwrap();
+        let counts = |settime, epoll_ctl| crate::syscalls::Counts {
+            settime,
+            epoll_ctl,
+            ..Default::default()
+        };
+        assert_eq!(waiting, counts(1, 1));
+        assert_eq!(rearmed, counts(1, 0));
+    }
 }
@@ -0,0 +1,108 @@
+use crate::{ClockId, TimerFd};
+use futures::{try_ready, Async, Poll, Stream};
+use std::io::Error as IoError;
+use std::time::Instant;
+use timerfd::{SetTimeFlags, TimerState};
+
+/// A deadline reached by a `DualDeadline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The soft deadline passed, e.g. time to warn or start cleaning up.
+    Soft,
+    /// The hard deadline passed, e.g. time to abort.
+    Hard,
+}
+
+/// A stream yielding `Phase::Soft` at a soft deadline and `Phase::Hard` at a
+/// later hard deadline, after which it ends.
+///
+/// Both deadlines are driven by a single timerfd, re-armed for the hard
+/// deadline once the soft one has passed.
+pub struct DualDeadline {
+    timerfd: TimerFd,
+    soft: Instant,
+    hard: Instant,
+    next: Option<Phase>,
+    armed: bool,
+}
+
+impl DualDeadline {
+    /// Create a new `DualDeadline` with the given deadlines.
+    ///
+    /// A soft deadline after the hard one is moved to the hard deadline, so
+    /// both phases are still yielded in order.
+    pub fn new(soft: Instant, hard: Instant) -> Result<DualDeadline, IoError> {
+        Ok(DualDeadline {
+            timerfd: TimerFd::new(ClockId::Monotonic)?,
+            soft: soft.min(hard),
+            hard,
+            next: Some(Phase::Soft),
+            armed: false,
+        })
+    }
+
+    /// Returns the soft deadline.
+    pub fn soft(&self) -> Instant {
+        self.soft
+    }
+
+    /// Returns the hard deadline.
+    pub fn hard(&self) -> Instant {
+        self.hard
+    }
+}
+
+impl Stream for DualDeadline {
+    type Item = Phase;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let (phase, deadline) = match self.next {
+                Some(Phase::Soft) => (Phase::Soft, self.soft),
+                Some(Phase::Hard) => (Phase::Hard, self.hard),
+                None => return Ok(Async::Ready(None)),
+            };
+            let now = Instant::now();
+            if now >= deadline {
+                self.next = match phase {
+                    Phase::Soft => Some(Phase::Hard),
+                    Phase::Hard => None,
+                };
+                self.armed = false;
+                return Ok(Async::Ready(Some(phase)));
+            }
+            if !self.armed {
+                let state = TimerState::Oneshot(deadline - now);
+                self.timerfd.set_state(state, SetTimeFlags::Default);
+                self.armed = true;
+            }
+            try_ready!(self.timerfd.poll_read());
+            self.armed = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::runtime::current_thread;
+
+    #[test]
+    fn yields_soft_then_hard_then_ends() {
+        let start = Instant::now();
+        let soft = start + Duration::from_millis(20);
+        let hard = start + Duration::from_millis(50);
+        let phases = DualDeadline::new(soft, hard)
+            .unwrap()
+            .map(|phase| (phase, Instant::now()));
+        let phases = current_thread::block_on_all(phases.collect()).unwrap();
+        assert_eq!(phases.len(), 2);
+        assert_eq!(phases[0].0, Phase::Soft);
+        assert_eq!(phases[1].0, Phase::Hard);
+        assert!(phases[0].1 >= soft && phases[0].1 < hard);
+        assert!(phases[1].1 >= hard);
+        assert!(phases[1].1 < hard + Duration::from_millis(30));
+    }
+}
@@ -0,0 +1,60 @@
+use std::error::Error;
+use std::fmt;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use timerfd::ClockId;
+
+/// Error reported by the timers in this crate.
+///
+/// Unlike `std::io::Error`, `TimerError` is `Clone`, so it can be broadcast to
+/// several waiters.
+#[derive(Debug, Clone)]
+pub enum TimerError {
+    /// The timerfd or the reactor driving it failed.
+    Io(Arc<IoError>),
+    /// Creating a timerfd on `clock` failed.
+    ///
+    /// Constructors return this wrapped in an `io::Error` of the same kind
+    /// as `source`. The wrapping `io::Error` has no `raw_os_error()`, use
+    /// `TimerError::raw_os_error` to match on the errno.
+    ClockInit {
+        clock: ClockId,
+        source: Arc<IoError>,
+    },
+}
+
+impl TimerError {
+    /// Returns the OS error code of the underlying `io::Error`, if any.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            TimerError::Io(err) => err.raw_os_error(),
+            TimerError::ClockInit { source, .. } => source.raw_os_error(),
+        }
+    }
+}
+
+impl fmt::Display for TimerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimerError::Io(err) => write!(f, "timer error: {}", err),
+            TimerError::ClockInit { clock, source } => {
+                write!(f, "failed to create timer on {}: {}", clock, source)
+            }
+        }
+    }
+}
+
+impl Error for TimerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TimerError::Io(err) => Some(&**err),
+            TimerError::ClockInit { source, .. } => Some(&**source),
+        }
+    }
+}
+
+impl From<IoError> for TimerError {
+    fn from(err: IoError) -> TimerError {
+        TimerError::Io(Arc::new(err))
+    }
+}
@@ -1,15 +1,185 @@
-use crate::{ClockId, TimerFd};
-use futures::{try_ready, Async, Stream};
-use std::io::Error as IoError;
-use std::time::{Duration, Instant};
+use crate::{
+    duration_from_nanos, instant_from_system_time, resolution_contract, system_time_from_instant,
+    Clock, ClockDelay, ClockId, TimerFd,
+};
+use futures::task::AtomicTask;
+use futures::{try_ready, Async, AsyncSink, Future, Sink, Stream};
+use log::warn;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{Error as IoError, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use timerfd::{SetTimeFlags, TimerState};
 
 /// A stream representing notifications at fixed interval
 pub struct Interval {
-    timerfd: TimerFd,
+    timerfd: Option<TimerFd>,
+    clock: ClockId,
     at: Instant,
     duration: Duration,
+    wall_anchor: Option<SystemTime>,
+    stats: Option<TickStats>,
+    pull: bool,
+    scheduled: Instant,
     initialized: bool,
+    stopped: bool,
+    final_tick: bool,
+    skip: u64,
+    /// Warmup not yet started, then its end once the timer is armed.
+    warmup: Option<Duration>,
+    warmup_until: Option<Instant>,
+    warmup_ticks: u64,
+    period_fn: Option<PeriodFn>,
+    source: Option<ClockSource>,
+}
+
+/// How well the consumer of an `Interval` keeps up, tracked from the first
+/// tick on.
+struct TickStats {
+    /// Moving average of expirations per read, see `Interval::pressure`.
+    pressure: f64,
+    /// Whether each of the last `ON_TIME_WINDOW` reads was on time.
+    recent: VecDeque<bool>,
+}
+
+impl TickStats {
+    fn record(&mut self, expirations: u64) {
+        self.pressure += PRESSURE_SMOOTHING * (expirations as f64 - self.pressure);
+        if self.recent.len() == ON_TIME_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(expirations == 1);
+    }
+}
+
+/// The clock driving an `Interval` created with
+/// `Interval::new_with_clock_source`, and the wait for its next tick.
+struct ClockSource {
+    clock: Arc<dyn Clock>,
+    /// Created on the next poll if `None`, e.g. after `reset_at`.
+    delay: Option<ClockDelay>,
+    /// The task polling the interval, woken by `reset_at`.
+    task: AtomicTask,
+}
+
+/// The schedule of an `Interval` created with `Interval::with_period_fn`.
+struct PeriodFn {
+    f: Box<dyn FnMut(u64) -> Duration + Send>,
+    /// Index of the next tick.
+    next: u64,
+    /// Nominal time of the previous tick, until the period following it is
+    /// computed.
+    previous: Option<Instant>,
+}
+
+/// An item yielded by an `Interval` created with `Interval::with_start_tick`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tick {
+    /// Emitted once, on the first poll, before any regular tick.
+    Start {
+        /// The instant the stream was first polled.
+        at: Instant,
+    },
+    /// A regular tick of the interval.
+    Regular,
+}
+
+/// A stream that yields a `Tick::Start` followed by the ticks of an
+/// `Interval`.
+///
+/// Created by `Interval::with_start_tick`.
+pub struct WithStartTick {
+    interval: Interval,
+    started: bool,
+}
+
+/// A stream yielding ticks of an `Interval` that must be acknowledged before
+/// the next one is delivered.
+///
+/// Created by `Interval::with_ack`.
+pub struct AckInterval {
+    interval: Interval,
+    outstanding: Option<Arc<AckState>>,
+}
+
+/// A tick delivered by an `AckInterval`.
+///
+/// Call `ack` once the tick has been processed. A tick dropped without
+/// being acknowledged is delivered again.
+pub struct TickAck {
+    state: Arc<AckState>,
+}
+
+struct AckState {
+    acked: AtomicBool,
+    released: AtomicBool,
+    task: AtomicTask,
+}
+
+/// A stream yielding how late every tick of an `Interval` was observed, in
+/// signed nanoseconds.
+///
+/// Created by `Interval::error_stream`.
+pub struct ErrorStream {
+    interval: Interval,
+}
+
+/// A stream yielding the time measured between consecutive ticks of an
+/// `Interval`.
+///
+/// Created by `Interval::gaps`.
+pub struct Gaps {
+    interval: Interval,
+    last: Option<Instant>,
+}
+
+/// A future forwarding the ticks of an `Interval` into a sink.
+///
+/// Created by `Interval::forward_to`.
+pub struct ForwardTo<S> {
+    interval: Interval,
+    sink: S,
+    buffered: Option<Instant>,
+}
+
+/// An item of a `FairInterval`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FairItem<T> {
+    /// A tick of the interval.
+    Tick,
+    /// An item of the merged stream.
+    Item(T),
+}
+
+/// An `Interval` merged with another stream, with bounded tick latency.
+///
+/// Created by [`fair_interval`].
+///
+/// [`fair_interval`]: fn.fair_interval.html
+pub struct FairInterval<S> {
+    interval: Interval,
+    stream: Option<S>,
+}
+
+/// Weight of the most recent read in the `pressure` moving average.
+const PRESSURE_SMOOTHING: f64 = 0.25;
+
+/// Number of recent reads considered by `on_time_ratio`.
+const ON_TIME_WINDOW: usize = 32;
+
+/// A wall-clock description of an `Interval` schedule.
+///
+/// The descriptor holds plain data and can be persisted in any format.
+/// Recreating an interval from it with `Interval::from_descriptor` yields ticks
+/// at `anchor + n * period`, so the phase survives a process restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleDescriptor {
+    /// Wall-clock instant of a tick the schedule is aligned to.
+    pub anchor: SystemTime,
+    /// Time between consecutive ticks.
+    pub period: Duration,
 }
 
 impl Interval {
@@ -21,17 +191,42 @@ impl Interval {
     ///
     /// This function panics if `duration` is zero.
     pub fn new(at: Instant, duration: Duration) -> Result<Interval, IoError> {
-        let timerfd = TimerFd::new(ClockId::Monotonic)?;
-        assert!(
-            duration > Duration::new(0, 0),
-            "`duration` must be non-zero."
-        );
-        Ok(Interval {
-            timerfd,
+        let mut interval = Interval::lazy(at, duration);
+        interval.timerfd = Some(TimerFd::new(ClockId::Monotonic)?);
+        Ok(interval)
+    }
+
+    /// Create a new `Interval` like `Interval::new`, but without allocating a
+    /// timer.
+    ///
+    /// The underlying timerfd is created on the first call to `poll`, so a
+    /// lazy `Interval` that is never polled costs no file descriptor. Errors
+    /// creating the timer are reported from `poll`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `duration` is zero.
+    pub fn lazy(at: Instant, duration: Duration) -> Interval {
+        assert!(!duration.is_zero(), "`duration` must be non-zero.");
+        Interval {
+            timerfd: None,
+            clock: ClockId::Monotonic,
             at,
             duration,
+            wall_anchor: None,
+            stats: None,
+            pull: false,
+            scheduled: at,
             initialized: false,
-        })
+            stopped: false,
+            final_tick: false,
+            skip: 0,
+            warmup: None,
+            warmup_until: None,
+            warmup_ticks: 0,
+            period_fn: None,
+            source: None,
+        }
     }
 
     /// Creates new `Interval` that yields with interval of `duration`.
@@ -47,26 +242,481 @@ impl Interval {
         Self::new(Instant::now() + duration, duration)
     }
 
-    pub fn new_interval_clock(duration: Duration, clockId: ClockId) -> Result<Interval, IoError> {
-        let timerfd = TimerFd::new(clockId)?;
-        assert!(
-            duration > Duration::new(0, 0),
-            "`duration` must be non-zero."
-        );
-        Ok(Interval {
-            timerfd,
-            at: Instant::now(),
-            duration,
-            initialized: false,
+    pub fn new_interval_clock(duration: Duration, clock_id: ClockId) -> Result<Interval, IoError> {
+        let mut interval = Interval::lazy(Instant::now(), duration);
+        interval.timerfd = Some(TimerFd::new(clock_id.clone())?);
+        interval.clock = clock_id;
+        Ok(interval)
+    }
+
+    /// Creates new `Interval` that paces ticks by the consumer.
+    ///
+    /// A regular interval is driven by a periodic kernel timer, which keeps
+    /// counting while the consumer is busy. This interval re-arms a oneshot
+    /// timer whenever it is polled after a tick was consumed, so each tick
+    /// comes `duration` after the consumer asked for it. The first tick comes
+    /// `duration` from now.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `duration` is zero.
+    pub fn new_pull(duration: Duration) -> Result<Interval, IoError> {
+        let mut interval = Self::new_interval(duration)?;
+        interval.pull = true;
+        Ok(interval)
+    }
+
+    /// Creates new `Interval` that yields every `period`, but silently drops
+    /// the first `n` ticks.
+    ///
+    /// The timer runs from the start, so the first tick comes `(n + 1) *
+    /// period` from now and later ticks keep the phase of the full schedule.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero.
+    pub fn skip_first(period: Duration, n: u64) -> Result<Interval, IoError> {
+        let mut interval = Self::new_interval(period)?;
+        interval.skip = n;
+        Ok(interval)
+    }
+
+    /// Creates new `Interval` that yields every `period` once `warmup` has
+    /// passed.
+    ///
+    /// Ticks scheduled during the first `warmup` of the schedule aren't
+    /// yielded but counted, see `warmup_ticks`. The timer runs from the
+    /// start, so the ticks after the warmup keep the phase of the full
+    /// schedule.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero.
+    pub fn with_warmup(period: Duration, warmup: Duration) -> Result<Interval, IoError> {
+        let mut interval = Self::new_interval(period)?;
+        interval.warmup = Some(warmup);
+        Ok(interval)
+    }
+
+    /// Creates new `Interval` whose ticks are spaced by `f`.
+    ///
+    /// `f(n)` is the period before tick `n`, so the first tick is at `start +
+    /// f(0)` and tick `n` follows tick `n - 1` by `f(n)`. A oneshot timer is
+    /// armed for every tick. As with `Interval::new_pull`, a tick the
+    /// consumer polls for only once it is due is scheduled `f(n)` from then.
+    ///
+    /// A zero period would tick in a busy loop and is rejected with an
+    /// `InvalidInput` error, returned here for `f(0)` and from the stream
+    /// for later ticks.
+    pub fn with_period_fn<F>(start: Instant, mut f: F) -> Result<Interval, IoError>
+    where
+        F: FnMut(u64) -> Duration + Send + 'static,
+    {
+        let period = checked_period(f(0))?;
+        let mut interval = Interval::lazy(start + period, period);
+        interval.timerfd = Some(TimerFd::new(ClockId::Monotonic)?);
+        interval.pull = true;
+        interval.period_fn = Some(PeriodFn {
+            f: Box::new(f),
+            next: 1,
+            previous: None,
+        });
+        Ok(interval)
+    }
+
+    /// Creates new `Interval` that yields every `period` of `clock`, starting
+    /// one period after the clock's current time.
+    ///
+    /// Waiting is delegated to the clock, so a `TestClock` drives the
+    /// interval without sleeping. Ticks missed while the consumer is busy are
+    /// coalesced and the schedule keeps its phase, as with a timerfd. No
+    /// timerfd is created unless the clock itself creates one.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero.
+    pub fn new_with_clock_source(clock: Arc<dyn Clock>, period: Duration) -> Interval {
+        let first = clock.now() + period;
+        let mut interval = Interval::lazy(first, period);
+        interval.source = Some(ClockSource {
+            clock,
+            delay: None,
+            task: AtomicTask::new(),
+        });
+        interval
+    }
+
+    /// Creates new `Interval` that yields `hz` times per second.
+    ///
+    /// The period is `1 / hz` seconds rounded to the nearest nanosecond, so
+    /// frequencies that don't divide a second evenly drift by up to half a
+    /// nanosecond per tick relative to the exact rate.
+    ///
+    /// An error of kind `InvalidInput` is returned if `hz` isn't a positive
+    /// finite number, if the period would be shorter than the clock
+    /// resolution reported by `resolution_contract`, or if it is too long to
+    /// schedule a tick.
+    pub fn from_hz(hz: f64) -> Result<Interval, IoError> {
+        Self::new_interval(period_from_hz(hz)?)
+    }
+
+    /// Creates new `Interval` that yields `target_fps` frames per second on
+    /// a fixed wall-clock phase.
+    ///
+    /// Frames land on multiples of the period since the Unix epoch. The
+    /// timer is armed with an absolute `CLOCK_REALTIME` deadline, see
+    /// `Interval::from_descriptor`, so frames don't drift and every process
+    /// using the same rate renders in phase. Missed frames are coalesced.
+    ///
+    /// The rate is validated like the one of `Interval::from_hz`.
+    pub fn fps(target_fps: f64) -> Result<Interval, IoError> {
+        Self::from_descriptor(ScheduleDescriptor {
+            anchor: UNIX_EPOCH,
+            period: period_from_hz(target_fps)?,
         })
     }
-}
 
-impl Stream for Interval {
-    type Item = ();
-    type Error = IoError;
+    /// Creates new `Interval` from a previously exported schedule.
+    ///
+    /// The interval uses `CLOCK_REALTIME` and is armed with an absolute
+    /// deadline, so ticks land on `desc.anchor + n * desc.period` even if the
+    /// anchor lies before the current process started. Ticks that would have
+    /// happened before the interval is first polled are skipped.
+    ///
+    /// An error of kind `InvalidInput` is returned if the period is zero, the
+    /// anchor precedes the Unix epoch, or the ticks following the next one
+    /// can't be represented as an `Instant` or `SystemTime`.
+    pub fn from_descriptor(desc: ScheduleDescriptor) -> Result<Interval, IoError> {
+        if desc.period.is_zero() {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "`period` must be non-zero.",
+            ));
+        }
+        if desc.anchor < UNIX_EPOCH {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "`anchor` must not precede the Unix epoch.",
+            ));
+        }
+        let now = SystemTime::now();
+        let representable = next_wall_tick(desc.anchor, desc.period, now)
+            .and_then(|next| next.checked_add(desc.period))
+            .and_then(|after| Instant::now().checked_add(after.duration_since(now).ok()?));
+        if representable.is_none() {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "`period` is too long to schedule a tick.",
+            ));
+        }
+        let at = instant_from_system_time(desc.anchor);
+        let mut interval = Interval::lazy(at, desc.period);
+        interval.timerfd = Some(TimerFd::new(ClockId::Realtime)?);
+        interval.clock = ClockId::Realtime;
+        interval.wall_anchor = Some(desc.anchor);
+        Ok(interval)
+    }
 
-    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+    /// Exports the schedule of this interval as a wall-clock descriptor.
+    ///
+    /// For intervals created from a descriptor the original anchor is
+    /// returned unchanged. For monotonic intervals the anchor is translated to
+    /// wall-clock time at the moment of the call.
+    pub fn schedule_descriptor(&self) -> ScheduleDescriptor {
+        let anchor = self
+            .wall_anchor
+            .unwrap_or_else(|| system_time_from_instant(self.at));
+        ScheduleDescriptor {
+            anchor,
+            period: self.duration,
+        }
+    }
+
+    /// Creates new `Interval` with the given `duration` whose first tick
+    /// coincides with the next tick of `other`.
+    ///
+    /// If both intervals share the same period their ticks coincide from then
+    /// on; otherwise they stay in a fixed phase relationship.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `duration` is zero.
+    pub fn aligned_to(other: &Interval, duration: Duration) -> Result<Interval, IoError> {
+        Self::new(other.next_tick_time(), duration)
+    }
+
+    /// Returns how well the consumer keeps up with the interval.
+    ///
+    /// This is an exponential moving average of the number of periods that
+    /// elapsed between consecutive reads. A value of 1.0 means every tick is
+    /// consumed on time; a value above 1.0 means ticks are being coalesced
+    /// because the consumer falls behind.
+    pub fn pressure(&self) -> f64 {
+        self.stats.as_ref().map_or(1.0, |stats| stats.pressure)
+    }
+
+    /// Returns the fraction of recent ticks that were consumed on time.
+    ///
+    /// A tick is on time if exactly one period elapsed since the previous
+    /// read and coalesced otherwise. The ratio covers the last 32 ticks and
+    /// is 1.0 before the first one.
+    pub fn on_time_ratio(&self) -> f64 {
+        let recent = match &self.stats {
+            Some(stats) => &stats.recent,
+            None => return 1.0,
+        };
+        let on_time = recent.iter().filter(|&&on_time| on_time).count();
+        on_time as f64 / recent.len() as f64
+    }
+
+    /// Returns the number of ticks suppressed during the warmup of an
+    /// interval created with `Interval::with_warmup`.
+    pub fn warmup_ticks(&self) -> u64 {
+        self.warmup_ticks
+    }
+
+    /// Arms the timer and registers it with the reactor of the current
+    /// thread, or the fallback reactor outside of a runtime, ahead of the
+    /// first poll.
+    ///
+    /// This doesn't need to run inside a task, so latency-critical code can
+    /// do the setup work up front and the first poll only checks for the
+    /// tick. Priming an interval that is already armed or stopped, or one
+    /// driven by a clock source, does nothing.
+    pub fn prime(&mut self) -> Result<(), IoError> {
+        if self.stopped || self.initialized || self.source.is_some() {
+            return Ok(());
+        }
+        self.init()?;
+        self.timerfd()?.register()
+    }
+
+    /// Stop the interval.
+    ///
+    /// The timer is disarmed and the stream ends on the next poll.
+    pub fn stop(&mut self) {
+        if let Some(source) = &mut self.source {
+            source.delay = None;
+        }
+        if let (true, Some(timerfd)) = (self.initialized, &mut self.timerfd) {
+            timerfd.set_state(TimerState::Disarmed, SetTimeFlags::Default);
+        }
+        self.initialized = false;
+        self.stopped = true;
+        self.final_tick = false;
+    }
+
+    /// Disarm the interval and return the number of expirations not yet
+    /// yielded.
+    ///
+    /// The stream ends on the next poll. Re-arming a timerfd resets its
+    /// expiration count, so the count is drained before disarming, and any
+    /// expiration that slips in between is inferred from the state the
+    /// timer had when it was disarmed. No tick is lost or counted twice.
+    pub fn finalize(&mut self) -> Result<u64, IoError> {
+        let mut pending = 0;
+        if let (Some(source), false) = (&mut self.source, self.stopped) {
+            // Every period that passed since the next tick was due.
+            if let Some(late) = source.clock.now().checked_duration_since(self.scheduled) {
+                let periods = late.as_nanos() / self.duration.as_nanos() + 1;
+                pending = u64::try_from(periods).unwrap_or(u64::MAX);
+            }
+            source.delay = None;
+        } else if let (true, false, Some(timerfd)) =
+            (self.initialized, self.stopped, &mut self.timerfd)
+        {
+            pending = timerfd.read_now()?;
+            let period = self.duration.as_nanos();
+            let expected = self.scheduled + duration_from_nanos(period * u128::from(pending));
+            match timerfd.set_state(TimerState::Disarmed, SetTimeFlags::Default) {
+                TimerState::Periodic { current, .. } => {
+                    let next = Instant::now() + current;
+                    if let Some(late) = next.checked_duration_since(expected) {
+                        // Round to absorb the jitter of `scheduled`.
+                        let slipped = (late.as_nanos() + period / 2) / period;
+                        pending += u64::try_from(slipped).unwrap_or(u64::MAX);
+                    }
+                }
+                // A oneshot timer that expired since the read.
+                TimerState::Disarmed if self.pull && pending == 0 => pending = 1,
+                _ => {}
+            }
+        }
+        self.initialized = false;
+        self.stopped = true;
+        self.final_tick = false;
+        Ok(pending)
+    }
+
+    /// Stop the interval after one last tick.
+    ///
+    /// Like `stop`, but the next poll yields one more tick right away, without
+    /// waiting for the period, before the stream ends. This gives consumers a
+    /// chance to flush pending work on shutdown.
+    pub fn stop_with_final_tick(&mut self) {
+        let final_tick = !self.stopped;
+        self.stop();
+        self.final_tick = final_tick;
+    }
+
+    /// Returns the instant the schedule is anchored to.
+    ///
+    /// Ticks are scheduled at `anchor() + n * period()`. If the anchor passed
+    /// before the interval was first polled, it is moved to the first tick
+    /// actually scheduled once the interval is polled.
+    pub fn anchor(&self) -> Instant {
+        self.at
+    }
+
+    /// Returns the time between consecutive ticks.
+    pub fn period(&self) -> Duration {
+        self.duration
+    }
+
+    /// Re-anchor the interval so that it ticks at `at` and every period
+    /// after that.
+    ///
+    /// Ticks of the previous schedule that haven't been consumed are
+    /// discarded. An interval that has been polled is re-armed right away,
+    /// so a task waiting for the old schedule is woken by the new one.
+    pub fn reset_at(&mut self, at: Instant) {
+        self.at = at;
+        self.scheduled = at;
+        self.wall_anchor = None;
+        self.initialized = false;
+        if let Some(source) = &mut self.source {
+            // The delay for the old schedule is replaced on the next poll.
+            source.delay = None;
+            source.task.notify();
+            return;
+        }
+        if self.timerfd.is_some() && !self.stopped && self.init().is_err() {
+            // The next poll retries and reports the error.
+            self.initialized = false;
+        }
+    }
+
+    /// Returns the instant at which the interval will yield next.
+    ///
+    /// Once the interval has been polled the value is read back from the
+    /// kernel timer. Before that it is derived from the constructor
+    /// arguments. An interval driven by a clock source reports the tick on
+    /// that clock's timeline.
+    pub fn next_tick_time(&self) -> Instant {
+        if self.source.is_some() {
+            return self.scheduled;
+        }
+        let now = Instant::now();
+        if let (true, Some(timerfd)) = (self.initialized, &self.timerfd) {
+            if let TimerState::Periodic { current, .. } = timerfd.get_state() {
+                return now + current;
+            }
+        }
+        if let Some(anchor) = self.wall_anchor {
+            let next = next_wall_tick(anchor, self.duration, SystemTime::now());
+            instant_from_system_time(next.expect("period is checked by `from_descriptor`"))
+        } else if self.at > now {
+            self.at
+        } else {
+            now + self.duration
+        }
+    }
+
+    /// Turns this interval into a stream that first yields a `Tick::Start`,
+    /// immediately on the first poll, and then `Tick::Regular` for every tick
+    /// of the interval.
+    ///
+    /// This lets a consumer run its setup from the same loop that handles
+    /// regular ticks.
+    pub fn with_start_tick(self) -> WithStartTick {
+        WithStartTick {
+            interval: self,
+            started: false,
+        }
+    }
+
+    /// Turns this interval into a stream yielding, for every tick, the real
+    /// time elapsed since the previous tick.
+    ///
+    /// The first item is the time since the stream was first polled. Unlike
+    /// the nominal period, the gaps include scheduling jitter and any time
+    /// the consumer spent between polls, which makes them suitable for
+    /// jitter analysis.
+    pub fn gaps(self) -> Gaps {
+        Gaps {
+            interval: self,
+            last: None,
+        }
+    }
+
+    /// Turns this interval into a stream yielding, for every tick, the signed
+    /// error in nanoseconds between the time the tick was observed and its
+    /// nominal time on the `anchor() + n * period()` schedule.
+    ///
+    /// A positive error means the tick was observed late. When ticks are
+    /// coalesced the error is measured against the latest missed tick, so a
+    /// consumer can feed it into a control loop adjusting the period.
+    pub fn error_stream(self) -> ErrorStream {
+        ErrorStream { interval: self }
+    }
+
+    /// Turns this interval into a stream whose ticks must be acknowledged.
+    ///
+    /// The timerfd isn't read while a tick is outstanding, so ticks the
+    /// kernel counts in the meantime are coalesced into the next one rather
+    /// than delivered back to back. This paces the consumer by how fast it
+    /// acknowledges, and a tick whose `TickAck` is dropped unacknowledged is
+    /// delivered again, giving at-least-once processing.
+    pub fn with_ack(self) -> AckInterval {
+        AckInterval {
+            interval: self,
+            outstanding: None,
+        }
+    }
+
+    /// Returns a future that sends the `Instant` of every tick into `sink`.
+    ///
+    /// The next tick isn't read until the sink has accepted the previous
+    /// one, so a slow sink causes ticks to be coalesced rather than queued.
+    /// The future completes once the sink fails, e.g. because its receiving
+    /// end was dropped, or once the interval is stopped and the sink is
+    /// closed.
+    pub fn forward_to<S: Sink<SinkItem = Instant>>(self, sink: S) -> ForwardTo<S> {
+        ForwardTo {
+            interval: self,
+            sink,
+            buffered: None,
+        }
+    }
+
+    /// Arms the timer unless it is already armed.
+    fn init(&mut self) -> Result<(), IoError> {
+        if !self.initialized {
+            if let Some(anchor) = self.wall_anchor {
+                let next =
+                    next_wall_tick(anchor, self.duration, SystemTime::now()).ok_or_else(|| {
+                        IoError::new(ErrorKind::InvalidInput, "next tick overflows the clock")
+                    })?;
+                let deadline = next
+                    .duration_since(UNIX_EPOCH)
+                    .expect("anchor is checked against the epoch");
+                let state = TimerState::Periodic {
+                    current: deadline,
+                    interval: self.duration,
+                };
+                self.timerfd()?.set_abstime(state)?;
+                self.scheduled = instant_from_system_time(next);
+                self.initialized = true;
+            }
+        }
+        if let (false, Some(period_fn)) = (self.initialized, &mut self.period_fn) {
+            if let Some(previous) = period_fn.previous.take() {
+                let period = checked_period((period_fn.f)(period_fn.next))?;
+                period_fn.next += 1;
+                self.at = previous + period;
+                self.duration = period;
+            }
+        }
         if !self.initialized {
             let now = Instant::now();
             let mut first_duration = if self.at > now {
@@ -74,28 +724,424 @@ impl Stream for Interval {
             } else {
                 self.duration
             };
-            if first_duration == Duration::from_millis(0) {
+            if first_duration.is_zero() {
                 first_duration = self.duration
             }
-            self.timerfd.set_state(
+            let state = if self.pull {
+                TimerState::Oneshot(first_duration)
+            } else {
                 TimerState::Periodic {
                     current: first_duration,
                     interval: self.duration,
-                },
-                SetTimeFlags::Default,
-            );
+                }
+            };
+            self.timerfd()?.set_state(state, SetTimeFlags::Default);
+            self.scheduled = now + first_duration;
+            // An anchor that has already passed is replaced by the first
+            // tick actually scheduled.
+            self.at = self.at.max(self.scheduled);
             self.initialized = true;
         }
-        try_ready!(self.timerfd.poll_read());
+        if let Some(warmup) = self.warmup.take() {
+            // The warmup starts with the schedule, one period before the
+            // first tick.
+            self.warmup_until = Some(self.scheduled - self.duration + warmup);
+        }
+        Ok(())
+    }
+
+    /// Re-arms an interval whose timer was found disarmed, so that it
+    /// doesn't silently stop ticking.
+    ///
+    /// Arming panics rather than leaving the timer disarmed, so this only
+    /// guards against the kernel timer being disarmed behind the interval's
+    /// back.
+    ///
+    /// A disarmed timer never wakes the task, so this happens on the first
+    /// poll a full period after a tick was due.
+    ///
+    /// The schedule keeps its phase, ticks missed while the timer was
+    /// disarmed are dropped.
+    fn rearm(&mut self) -> Result<Async<Option<()>>, IoError> {
+        let now = Instant::now();
+        let mut next = self.scheduled;
+        if next <= now {
+            let periods = (now - next).as_nanos() / self.duration.as_nanos() + 1;
+            next += duration_from_nanos(self.duration.as_nanos() * periods);
+        }
+        warn!(
+            "interval timer found disarmed, re-arming for {:?}",
+            next - now
+        );
+        self.at = next;
+        self.initialized = false;
+        self.poll()
+    }
+
+    fn timerfd(&mut self) -> Result<&mut TimerFd, IoError> {
+        if self.timerfd.is_none() {
+            self.timerfd = Some(TimerFd::new(self.clock.clone())?);
+        }
+        Ok(self.timerfd.as_mut().unwrap())
+    }
+}
+
+/// Returns the period of a rate of `hz` ticks per second.
+fn period_from_hz(hz: f64) -> Result<Duration, IoError> {
+    if !(hz.is_finite() && hz > 0.0) {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "`hz` must be positive and finite.",
+        ));
+    }
+    let nanos = (1e9 / hz).round();
+    let resolution = resolution_contract();
+    if nanos < resolution.as_nanos() as f64 {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "`hz` exceeds the clock resolution.",
+        ));
+    }
+    let period = duration_from_nanos(nanos as u128);
+    if Instant::now().checked_add(period).is_none() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "`hz` is too low to represent its period.",
+        ));
+    }
+    Ok(period)
+}
+
+/// Rejects a zero period returned by the function of
+/// `Interval::with_period_fn`.
+fn checked_period(period: Duration) -> Result<Duration, IoError> {
+    if period.is_zero() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "interval period must be non-zero",
+        ));
+    }
+    Ok(period)
+}
+
+/// Returns the first tick of the `anchor + n * period` schedule after `now`,
+/// or `None` if it can't be represented.
+fn next_wall_tick(anchor: SystemTime, period: Duration, now: SystemTime) -> Option<SystemTime> {
+    match now.duration_since(anchor) {
+        Err(_) => Some(anchor),
+        Ok(elapsed) => {
+            let periods = elapsed.as_nanos() / period.as_nanos() + 1;
+            let offset = period.as_nanos().checked_mul(periods)?;
+            anchor.checked_add(duration_from_nanos(offset))
+        }
+    }
+}
+
+impl Stream for Interval {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        if self.stopped {
+            if self.final_tick {
+                self.final_tick = false;
+                return Ok(Async::Ready(Some(())));
+            }
+            return Ok(Async::Ready(None));
+        }
+        if let Some(source) = &mut self.source {
+            source.task.register();
+            let (clock, scheduled) = (&source.clock, self.scheduled);
+            let delay = source.delay.get_or_insert_with(|| clock.delay(scheduled));
+            try_ready!(delay.poll());
+            let late = source.clock.now().saturating_duration_since(self.scheduled);
+            let periods = late.as_nanos() / self.duration.as_nanos() + 1;
+            self.scheduled += duration_from_nanos(self.duration.as_nanos() * periods);
+            source.delay = Some(source.clock.delay(self.scheduled));
+            return Ok(Async::Ready(Some(())));
+        }
+        self.init()?;
+        let expirations = match self.timerfd()?.poll_read()? {
+            Async::Ready(expirations) => expirations,
+            Async::NotReady => {
+                // Readiness is only delivered when the reactor gets to run,
+                // which never happens while the task is kept busy by an
+                // always-ready future. Check the timer directly once a tick
+                // is due so the interval isn't starved.
+                let now = Instant::now();
+                if now < self.scheduled {
+                    return Ok(Async::NotReady);
+                }
+                match self.timerfd()?.read_now()? {
+                    // Only a tick a full period overdue is worth asking the
+                    // kernel whether the timer is still armed.
+                    0 if now >= self.scheduled + self.duration
+                        && self.timerfd()?.get_state() == TimerState::Disarmed =>
+                    {
+                        return self.rearm()
+                    }
+                    0 => return Ok(Async::NotReady),
+                    expirations => expirations,
+                }
+            }
+        };
+        let first = self.scheduled;
+        self.scheduled += duration_from_nanos(self.duration.as_nanos() * u128::from(expirations));
+        let mut expirations = expirations;
+        if let Some(until) = self.warmup_until {
+            let period = self.duration.as_nanos();
+            let in_warmup = match until.checked_duration_since(first) {
+                Some(left) => left.as_nanos().div_ceil(period),
+                None => 0,
+            };
+            let in_warmup = u64::try_from(in_warmup)
+                .unwrap_or(u64::MAX)
+                .min(expirations);
+            self.warmup_ticks += in_warmup;
+            if in_warmup == expirations {
+                return self.poll();
+            }
+            self.warmup_until = None;
+            expirations -= in_warmup;
+        }
+        if self.skip > 0 {
+            let skipped = expirations.min(self.skip);
+            self.skip -= skipped;
+            if skipped == expirations {
+                return self.poll();
+            }
+        }
+        self.stats
+            .get_or_insert_with(|| TickStats {
+                pressure: 1.0,
+                recent: VecDeque::with_capacity(ON_TIME_WINDOW),
+            })
+            .record(expirations);
+        if self.pull {
+            // Re-arm on the next poll, once the consumer is ready for more.
+            self.initialized = false;
+        }
+        if let Some(period_fn) = &mut self.period_fn {
+            period_fn.previous = Some(self.scheduled - self.duration);
+        }
         Ok(Async::Ready(Some(())))
     }
 }
 
+impl Drop for Interval {
+    /// Disarms the timer before its fd is closed.
+    ///
+    /// This holds even if the interval is dropped between a poll that armed
+    /// the timer and the first tick, so the kernel timer never outlives the
+    /// stream, including through descriptors duplicated from its fd.
+    fn drop(&mut self) {
+        if let (true, Some(timerfd)) = (self.initialized, &mut self.timerfd) {
+            timerfd.set_state(TimerState::Disarmed, SetTimeFlags::Default);
+        }
+    }
+}
+
+impl<S: Sink<SinkItem = Instant>> Future for ForwardTo<S> {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        loop {
+            if let Some(at) = self.buffered.take() {
+                match self.sink.start_send(at) {
+                    Ok(AsyncSink::Ready) => {}
+                    Ok(AsyncSink::NotReady(at)) => {
+                        self.buffered = Some(at);
+                        return match self.sink.poll_complete() {
+                            Ok(_) => Ok(Async::NotReady),
+                            Err(_) => Ok(Async::Ready(())),
+                        };
+                    }
+                    Err(_) => return Ok(Async::Ready(())),
+                }
+            }
+            match self.interval.poll()? {
+                Async::Ready(Some(())) => self.buffered = Some(Instant::now()),
+                Async::Ready(None) => {
+                    // Flush the ticks sent so far before completing.
+                    return match self.sink.close() {
+                        Ok(Async::NotReady) => Ok(Async::NotReady),
+                        _ => Ok(Async::Ready(())),
+                    };
+                }
+                Async::NotReady => {
+                    return match self.sink.poll_complete() {
+                        Ok(_) => Ok(Async::NotReady),
+                        Err(_) => Ok(Async::Ready(())),
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Merges `interval` with `stream`, giving ticks priority once they are due.
+///
+/// `Stream::select` alternates between its two streams, so a tick due while
+/// the other stream has items ready may wait behind one of them. A
+/// `FairInterval` polls the interval first as soon as a tick is due and the
+/// other stream first otherwise, so a tick is delivered on the first poll
+/// after it is due no matter how busy the other stream is. The stream ends
+/// once `stream` has ended and the interval is stopped.
+pub fn fair_interval<S>(interval: Interval, stream: S) -> FairInterval<S>
+where
+    S: Stream,
+    S::Error: From<IoError>,
+{
+    FairInterval {
+        interval,
+        stream: Some(stream),
+    }
+}
+
+impl<S> Stream for FairInterval<S>
+where
+    S: Stream,
+    S::Error: From<IoError>,
+{
+    type Item = FairItem<S::Item>;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        // An interval that hasn't been armed yet is polled first to arm it.
+        let due = !self.interval.initialized || Instant::now() >= self.interval.scheduled;
+        let mut tick = None;
+        if due {
+            tick = Some(self.interval.poll()?);
+            if let Some(Async::Ready(Some(()))) = tick {
+                return Ok(Async::Ready(Some(FairItem::Tick)));
+            }
+        }
+        if let Some(stream) = &mut self.stream {
+            match stream.poll()? {
+                Async::Ready(Some(item)) => return Ok(Async::Ready(Some(FairItem::Item(item)))),
+                Async::Ready(None) => self.stream = None,
+                Async::NotReady => {}
+            }
+        }
+        // Poll the interval unless that already happened above.
+        let tick = match tick {
+            Some(tick) => tick,
+            None => self.interval.poll()?,
+        };
+        match tick {
+            Async::Ready(Some(())) => Ok(Async::Ready(Some(FairItem::Tick))),
+            Async::Ready(None) if self.stream.is_none() => Ok(Async::Ready(None)),
+            Async::Ready(None) | Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Stream for WithStartTick {
+    type Item = Tick;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        if !self.started {
+            self.started = true;
+            return Ok(Async::Ready(Some(Tick::Start { at: Instant::now() })));
+        }
+        let tick = try_ready!(self.interval.poll());
+        Ok(Async::Ready(tick.map(|()| Tick::Regular)))
+    }
+}
+
+impl TickAck {
+    fn new() -> TickAck {
+        TickAck {
+            state: Arc::new(AckState {
+                acked: AtomicBool::new(false),
+                released: AtomicBool::new(false),
+                task: AtomicTask::new(),
+            }),
+        }
+    }
+
+    /// Acknowledge the tick, letting the `AckInterval` deliver the next one.
+    pub fn ack(self) {
+        self.state.acked.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for TickAck {
+    fn drop(&mut self) {
+        self.state.released.store(true, Ordering::Release);
+        self.state.task.notify();
+    }
+}
+
+impl Stream for AckInterval {
+    type Item = TickAck;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        if let Some(state) = &self.outstanding {
+            state.task.register();
+            if !state.released.load(Ordering::Acquire) {
+                return Ok(Async::NotReady);
+            }
+            if !state.acked.load(Ordering::Acquire) {
+                let tick = TickAck::new();
+                self.outstanding = Some(tick.state.clone());
+                return Ok(Async::Ready(Some(tick)));
+            }
+            self.outstanding = None;
+        }
+        if try_ready!(self.interval.poll()).is_none() {
+            return Ok(Async::Ready(None));
+        }
+        let tick = TickAck::new();
+        self.outstanding = Some(tick.state.clone());
+        Ok(Async::Ready(Some(tick)))
+    }
+}
+
+impl Stream for ErrorStream {
+    type Item = i128;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        if try_ready!(self.interval.poll()).is_none() {
+            return Ok(Async::Ready(None));
+        }
+        let now = Instant::now();
+        // `scheduled` has already moved past the tick just delivered.
+        let nominal = self.interval.scheduled - self.interval.duration;
+        let error = if now >= nominal {
+            (now - nominal).as_nanos() as i128
+        } else {
+            -((nominal - now).as_nanos() as i128)
+        };
+        Ok(Async::Ready(Some(error)))
+    }
+}
+
+impl Stream for Gaps {
+    type Item = Duration;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        let last = *self.last.get_or_insert_with(Instant::now);
+        if try_ready!(self.interval.poll()).is_none() {
+            return Ok(Async::Ready(None));
+        }
+        let now = Instant::now();
+        self.last = Some(now);
+        Ok(Async::Ready(Some(now - last)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::time::Instant;
     use tokio::prelude::*;
+    use tokio::runtime::current_thread;
 
     #[test]
     fn interval_works_zero() {
@@ -132,4 +1178,694 @@ mod tests {
                 })
         }));
     }
+
+    #[test]
+    fn schedule_descriptor_round_trip() {
+        let period = Duration::from_millis(50);
+        let interval = Interval::new(Instant::now() + period, period).unwrap();
+        let desc = interval.schedule_descriptor();
+        assert_eq!(desc.period, period);
+
+        let restored = Interval::from_descriptor(desc).unwrap();
+        assert_eq!(restored.schedule_descriptor(), desc);
+
+        let offsets = current_thread::block_on_all(
+            restored
+                .take(3)
+                .map(move |_| {
+                    let since_anchor = SystemTime::now().duration_since(desc.anchor).unwrap();
+                    since_anchor.as_nanos() % period.as_nanos()
+                })
+                .collect(),
+        )
+        .unwrap();
+        for offset in offsets {
+            println!("{:?}", Duration::from_nanos(offset as u64));
+            // Wakeups may run late on a loaded machine, but never early.
+            assert!(offset < Duration::from_millis(15).as_nanos());
+        }
+    }
+
+    #[test]
+    fn from_descriptor_rejects_zero_period() {
+        let desc = ScheduleDescriptor {
+            anchor: SystemTime::now(),
+            period: Duration::ZERO,
+        };
+        let err = Interval::from_descriptor(desc).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn from_descriptor_rejects_unrepresentable_ticks() {
+        let periods = [Duration::MAX, Duration::from_secs(u64::MAX / 2)];
+        for period in periods.iter() {
+            let desc = ScheduleDescriptor {
+                anchor: UNIX_EPOCH,
+                period: *period,
+            };
+            let err = Interval::from_descriptor(desc).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{:?}", period);
+        }
+    }
+
+    #[test]
+    fn interval_keeps_nanosecond_period() {
+        let period = Duration::new(1, 1);
+        let interval = current_thread::block_on_all(future::lazy(move || {
+            let mut interval = Interval::new_interval(period).unwrap();
+            assert!(interval.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(interval)
+        }))
+        .unwrap();
+        match interval.timerfd.as_ref().unwrap().get_state() {
+            TimerState::Periodic { interval, .. } => assert_eq!(interval, period),
+            state => panic!("unexpected state: {:?}", state),
+        }
+    }
+
+    #[test]
+    fn lazy_interval_allocates_on_poll() {
+        let now = Instant::now();
+        let interval = Interval::lazy(now, Duration::from_micros(10));
+        assert!(interval.timerfd.is_none());
+        let ticks: Vec<_> = current_thread::block_on_all(interval.take(2).collect()).unwrap();
+        assert_eq!(ticks.len(), 2);
+    }
+
+    #[test]
+    fn aligned_intervals_tick_together() {
+        let period = Duration::from_millis(20);
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let mut first = Interval::new_interval(period).unwrap();
+        assert!(rt
+            .block_on(future::lazy(|| first.poll()))
+            .unwrap()
+            .is_not_ready());
+        let second = Interval::aligned_to(&first, period).unwrap();
+        let ticks = rt
+            .block_on(
+                first
+                    .take(3)
+                    .map(|_| Instant::now())
+                    .collect()
+                    .join(second.take(3).map(|_| Instant::now()).collect()),
+            )
+            .unwrap();
+        for (a, b) in ticks.0.iter().zip(ticks.1.iter()) {
+            let skew = if a > b { *a - *b } else { *b - *a };
+            println!("{:?}", skew);
+            assert!(skew < Duration::from_millis(2));
+        }
+    }
+
+    #[test]
+    fn pressure_tracks_stalled_consumer() {
+        let mut rt = current_thread::Runtime::new().unwrap();
+        // A coarse period keeps scheduling noise from coalescing ticks.
+        let mut interval = Interval::new_interval(Duration::from_millis(20)).unwrap();
+        rt.block_on(interval.by_ref().take(5).for_each(|_| {
+            std::thread::sleep(Duration::from_millis(60));
+            Ok(())
+        }))
+        .unwrap();
+        let stalled = interval.pressure();
+        println!("{}", stalled);
+        assert!(stalled > 1.5);
+
+        rt.block_on(interval.by_ref().take(10).for_each(|_| Ok(())))
+            .unwrap();
+        let recovered = interval.pressure();
+        println!("{}", recovered);
+        assert!(recovered < stalled);
+        assert!(recovered < 1.5);
+    }
+
+    #[test]
+    fn on_time_ratio_tracks_coalescing() {
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let mut interval = Interval::new_interval(Duration::from_millis(10)).unwrap();
+        assert_eq!(interval.on_time_ratio(), 1.0);
+        // Nothing is tracked before the first tick.
+        assert!(interval.stats.is_none());
+        rt.block_on(interval.by_ref().take(5).for_each(|_| Ok(())))
+            .unwrap();
+        let fast = interval.on_time_ratio();
+        println!("{}", fast);
+        assert!(fast >= 0.8);
+
+        rt.block_on(interval.by_ref().take(5).for_each(|_| {
+            std::thread::sleep(Duration::from_millis(25));
+            Ok(())
+        }))
+        .unwrap();
+        let stalled = interval.on_time_ratio();
+        println!("{}", stalled);
+        assert!(stalled < fast);
+        assert!(stalled <= 0.6);
+    }
+
+    #[test]
+    fn pull_interval_waits_for_consumer() {
+        let period = Duration::from_millis(10);
+        let pause = Duration::from_millis(30);
+        let interval = Interval::new_pull(period).unwrap();
+        let ticks: Vec<_> = current_thread::block_on_all(
+            interval
+                .take(3)
+                .map(move |_| {
+                    let tick = Instant::now();
+                    std::thread::sleep(pause);
+                    tick
+                })
+                .collect(),
+        )
+        .unwrap();
+        for pair in ticks.windows(2) {
+            let gap = pair[1] - pair[0];
+            println!("{:?}", gap);
+            assert!(gap >= pause + period);
+        }
+    }
+
+    #[test]
+    fn interval_progresses_next_to_busy_stream() {
+        let window = Duration::from_millis(100);
+        let interval = Interval::new_interval(Duration::from_millis(10)).unwrap();
+        let busy = stream::repeat(false);
+        let start = Instant::now();
+        let ticks = current_thread::block_on_all(
+            interval
+                .map(|_| true)
+                .select(busy)
+                .take_while(move |_| Ok(start.elapsed() < window))
+                .filter(|is_tick| *is_tick)
+                .fold(0, |ticks, _| Ok::<_, IoError>(ticks + 1)),
+        )
+        .unwrap();
+        println!("{}", ticks);
+        // Nine ticks are due in the window, allow for a loaded machine.
+        assert!(ticks >= 4, "{}", ticks);
+    }
+
+    #[test]
+    fn start_tick_precedes_regular_ticks() {
+        let period = Duration::from_millis(10);
+        let created = Instant::now();
+        let ticks = Interval::new_interval(period)
+            .unwrap()
+            .with_start_tick()
+            .take(4)
+            .map(|tick| (tick, Instant::now()))
+            .collect();
+        let ticks = current_thread::block_on_all(ticks).unwrap();
+        let start = match ticks[0].0 {
+            Tick::Start { at } => at,
+            tick => panic!("unexpected first tick: {:?}", tick),
+        };
+        assert!(ticks[0].1 - start < period);
+        for (n, (tick, at)) in ticks.iter().enumerate().skip(1) {
+            assert_eq!(*tick, Tick::Regular);
+            assert!(*at >= created + period * n as u32, "{:?}", *at - created);
+        }
+    }
+
+    #[test]
+    fn from_hz_converts_to_period() {
+        let interval = Interval::from_hz(100.0).unwrap();
+        assert_eq!(interval.duration, Duration::from_millis(10));
+        let interval = Interval::from_hz(3.0).unwrap();
+        assert_eq!(interval.duration, Duration::from_nanos(333_333_333));
+    }
+
+    #[test]
+    fn from_hz_rejects_invalid_frequencies() {
+        let tiny = [1e-19, f64::MIN_POSITIVE];
+        for hz in [0.0, -50.0, f64::NAN, f64::INFINITY, 1e12]
+            .iter()
+            .chain(&tiny)
+        {
+            let err = Interval::from_hz(*hz).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", hz);
+        }
+    }
+
+    #[test]
+    fn gaps_measure_time_between_ticks() {
+        let period = Duration::from_millis(10);
+        let gaps = Interval::new_interval(period).unwrap().gaps().take(4);
+        let gaps = current_thread::block_on_all(gaps.collect()).unwrap();
+        // A late tick shortens the gap to the next one, so only the total
+        // is bounded from below.
+        let total: Duration = gaps.iter().sum();
+        assert!(total + period / 2 >= period * 4, "{:?}", gaps);
+        for gap in gaps {
+            assert!(gap < period * 3, "{:?}", gap);
+        }
+    }
+
+    #[test]
+    fn gaps_include_consumer_stalls() {
+        let period = Duration::from_millis(10);
+        let stall = Duration::from_millis(35);
+        let mut n = 0;
+        let gaps = Interval::new_interval(period)
+            .unwrap()
+            .gaps()
+            .take(3)
+            .inspect(move |_| {
+                n += 1;
+                if n == 1 {
+                    std::thread::sleep(stall);
+                }
+            });
+        let gaps = current_thread::block_on_all(gaps.collect()).unwrap();
+        assert!(gaps[0] < period * 3, "{:?}", gaps[0]);
+        assert!(gaps[1] >= stall, "{:?}", gaps[1]);
+        assert!(gaps[2] < period * 3, "{:?}", gaps[2]);
+    }
+
+    #[test]
+    fn dropped_interval_disarms_timer() {
+        use std::os::unix::io::AsRawFd;
+
+        let mut interval = Interval::new_interval(Duration::from_millis(50)).unwrap();
+        let fd = current_thread::block_on_all(future::lazy(|| {
+            assert!(interval.poll().unwrap().is_not_ready());
+            let raw = interval.timerfd.as_ref().unwrap().0.get_ref().0.as_raw_fd();
+            // A duplicate shares the kernel timer and outlives the interval.
+            let fd = rustix::io::dup(unsafe { std::os::unix::io::BorrowedFd::borrow_raw(raw) });
+            Ok::<_, ()>(fd.unwrap())
+        }))
+        .unwrap();
+        let armed = rustix::time::timerfd_gettime(&fd).unwrap();
+        assert!(armed.it_value.tv_sec != 0 || armed.it_value.tv_nsec != 0);
+        drop(interval);
+        let state = rustix::time::timerfd_gettime(&fd).unwrap();
+        assert_eq!((state.it_value.tv_sec, state.it_value.tv_nsec), (0, 0));
+        assert_eq!(
+            (state.it_interval.tv_sec, state.it_interval.tv_nsec),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn forward_to_respects_backpressure() {
+        use crate::Delay;
+        use futures::sync::mpsc;
+        use tokio::runtime::current_thread::Runtime;
+
+        let period = Duration::from_millis(5);
+        let mut rt = Runtime::new().unwrap();
+        let (tx, rx) = mpsc::channel(1);
+        let forward = Interval::new_interval(period).unwrap().forward_to(tx);
+        rt.spawn(forward.map_err(|err| panic!("{:?}", err)));
+        let received = rx.take(5).and_then(|at| {
+            // A slow consumer, several periods per tick.
+            Delay::new(Instant::now() + period * 3)
+                .map(move |_| at)
+                .map_err(|err| panic!("{:?}", err))
+        });
+        let ticks = rt.block_on(received.collect()).unwrap();
+        assert_eq!(ticks.len(), 5);
+        for pair in ticks.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+        // The receiver is gone, so forwarding ends.
+        rt.run().unwrap();
+    }
+
+    #[test]
+    fn forward_to_closes_sink_when_interval_ends() {
+        use futures::{AsyncSink, StartSend};
+        use std::sync::atomic::AtomicUsize;
+
+        struct Recorder {
+            sent: usize,
+            closes: Arc<AtomicUsize>,
+        }
+
+        impl Sink for Recorder {
+            type SinkItem = Instant;
+            type SinkError = ();
+
+            fn start_send(&mut self, _: Instant) -> StartSend<Instant, ()> {
+                self.sent += 1;
+                Ok(AsyncSink::Ready)
+            }
+
+            fn poll_complete(&mut self) -> Poll<(), ()> {
+                Ok(Async::Ready(()))
+            }
+
+            fn close(&mut self) -> Poll<(), ()> {
+                assert_eq!(self.sent, 1);
+                // Take two polls to close, like a sink flushing a buffer.
+                if self.closes.fetch_add(1, Ordering::SeqCst) == 0 {
+                    task::current().notify();
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(()))
+            }
+        }
+
+        let closes = Arc::new(AtomicUsize::new(0));
+        let mut interval = Interval::new_interval(Duration::from_secs(60)).unwrap();
+        interval.stop_with_final_tick();
+        let sink = Recorder {
+            sent: 0,
+            closes: closes.clone(),
+        };
+        current_thread::block_on_all(interval.forward_to(sink)).unwrap();
+        assert_eq!(closes.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn stop_with_final_tick_yields_once_more() {
+        let period = Duration::from_millis(10);
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let mut interval = Interval::new_interval(period).unwrap();
+        rt.block_on(interval.by_ref().take(2).for_each(|_| Ok(())))
+            .unwrap();
+        interval.stop_with_final_tick();
+        let now = Instant::now();
+        let rest = rt.block_on(interval.by_ref().collect()).unwrap();
+        assert_eq!(rest.len(), 1);
+        assert!(now.elapsed() < period);
+
+        let mut stopped = Interval::new_interval(period).unwrap();
+        stopped.stop();
+        stopped.stop_with_final_tick();
+        assert!(rt.block_on(stopped.collect()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn skip_first_keeps_phase() {
+        let period = Duration::from_millis(10);
+        let created = Instant::now();
+        let interval = Interval::skip_first(period, 3).unwrap();
+        let ticks = interval.take(3).map(|_| Instant::now()).collect();
+        let ticks = current_thread::block_on_all(ticks).unwrap();
+        for (n, tick) in ticks.iter().enumerate() {
+            let since = *tick - created;
+            assert!(since >= period * (n as u32 + 4), "{:?}", since);
+        }
+        assert!(ticks[0] - created < period * 6, "{:?}", ticks[0] - created);
+    }
+
+    #[test]
+    fn fair_interval_ticks_next_to_busy_stream() {
+        let window = Duration::from_millis(100);
+        let period = Duration::from_millis(10);
+        let busy = stream::repeat::<u32, IoError>(1);
+        let merged = fair_interval(Interval::new_interval(period).unwrap(), busy);
+        let start = Instant::now();
+        let ticks = merged
+            .take_while(move |_| Ok(start.elapsed() < window))
+            .filter(|item| *item == FairItem::Tick)
+            .fold(0, |ticks, _| Ok::<_, IoError>(ticks + 1));
+        let ticks = current_thread::block_on_all(ticks).unwrap();
+        // Nine ticks are due in the window, allow for a loaded machine.
+        assert!(ticks >= 4, "{}", ticks);
+    }
+
+    #[test]
+    fn anchor_follows_reset_at() {
+        let period = Duration::from_millis(10);
+        let at = Instant::now() + period;
+        let mut interval = Interval::new(at, period).unwrap();
+        assert_eq!(interval.anchor(), at);
+        assert_eq!(interval.period(), period);
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(interval.by_ref().take(1).for_each(|_| Ok(())))
+            .unwrap();
+        let reset = Instant::now() + period * 3;
+        interval.reset_at(reset);
+        assert_eq!(interval.anchor(), reset);
+        rt.block_on(interval.by_ref().take(1).for_each(|_| Ok(())))
+            .unwrap();
+        assert!(Instant::now() >= reset);
+        assert_eq!(interval.anchor(), reset);
+    }
+
+    #[test]
+    fn reset_at_wakes_waiting_task() {
+        let start = Instant::now();
+        let mut interval =
+            Interval::new(start + Duration::from_secs(1), Duration::from_secs(1)).unwrap();
+        let mut reset = false;
+        // Reset the pending interval without polling it again.
+        let tick = future::poll_fn(move || {
+            if reset {
+                return interval.poll();
+            }
+            assert!(interval.poll()?.is_not_ready());
+            interval.reset_at(Instant::now() + Duration::from_millis(20));
+            reset = true;
+            Ok::<_, IoError>(Async::NotReady)
+        });
+        current_thread::block_on_all(tick).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(
+            start.elapsed() < Duration::from_millis(500),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+
+    #[test]
+    fn creation_error_names_the_clock() {
+        crate::syscalls::set_unsupported(ClockId::BoottimeAlarm);
+        let err =
+            match Interval::new_interval_clock(Duration::from_millis(1), ClockId::BoottimeAlarm) {
+                Ok(_) => panic!("interval created on an unsupported clock"),
+                Err(err) => err,
+            };
+        assert!(err.to_string().contains("CLOCK_BOOTTIME_ALARM"), "{}", err);
+        let source = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<crate::TimerError>());
+        match source {
+            Some(crate::TimerError::ClockInit { clock, source }) => {
+                assert_eq!(*clock, ClockId::BoottimeAlarm);
+                assert_eq!(
+                    source.raw_os_error(),
+                    Some(rustix::io::Errno::INVAL.raw_os_error())
+                );
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn error_stream_reports_late_ticks() {
+        let period = Duration::from_millis(50);
+        let stall = Duration::from_millis(70);
+        let mut n = 0;
+        let errors = Interval::new_interval(period)
+            .unwrap()
+            .error_stream()
+            .take(2)
+            .inspect(move |_| {
+                n += 1;
+                if n == 1 {
+                    std::thread::sleep(stall);
+                }
+            });
+        let errors = current_thread::block_on_all(errors.collect()).unwrap();
+        let ms = Duration::from_millis(1).as_nanos() as i128;
+        assert!(errors[0].abs() < 30 * ms, "{:?}", errors);
+        // The second tick fired while the consumer was stalled, so it's
+        // reported at least as late as the stall outlasted the period.
+        assert!(errors[1] >= 20 * ms, "{:?}", errors);
+        assert!(errors[1] > errors[0], "{:?}", errors);
+        assert!(errors[1] < 55 * ms, "{:?}", errors);
+    }
+
+    #[test]
+    fn ack_interval_is_paced_by_acks() {
+        use crate::Delay;
+
+        let ack_after = Duration::from_millis(30);
+        let ticks = Interval::new_interval(Duration::from_millis(5))
+            .unwrap()
+            .with_ack()
+            .take(4)
+            .map(move |tick| {
+                let now = Instant::now();
+                let ack = Delay::new(now + ack_after).unwrap();
+                tokio::spawn(ack.map(move |()| tick.ack()).map_err(|_| ()));
+                now
+            });
+        let ticks = current_thread::block_on_all(ticks.collect()).unwrap();
+        for pair in ticks.windows(2) {
+            assert!(pair[1] - pair[0] >= ack_after, "{:?}", pair[1] - pair[0]);
+        }
+    }
+
+    #[test]
+    fn dropped_tick_is_redelivered() {
+        let ticks = Interval::new_interval(Duration::from_secs(60)).unwrap();
+        let mut ticks = ticks.with_ack();
+        let tick = TickAck::new();
+        ticks.outstanding = Some(tick.state.clone());
+        drop(tick);
+        let redelivered = current_thread::block_on_all(future::lazy(move || ticks.poll()));
+        assert!(redelivered.unwrap().map(|tick| tick.is_some()).is_ready());
+    }
+
+    #[test]
+    fn warmup_ticks_are_counted_not_yielded() {
+        let start = Instant::now();
+        let mut interval =
+            Interval::with_warmup(Duration::from_millis(10), Duration::from_millis(35)).unwrap();
+        let first = current_thread::block_on_all((&mut interval).take(1).collect()).unwrap();
+        assert_eq!(first.len(), 1);
+        assert!(start.elapsed() >= Duration::from_millis(35));
+        assert_eq!(interval.warmup_ticks(), 3);
+    }
+
+    #[test]
+    fn disarmed_interval_recovers() {
+        let period = Duration::from_millis(10);
+        let mut interval = Interval::new_interval(period).unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on((&mut interval).take(1).collect()).unwrap();
+
+        let timerfd = interval.timerfd.as_mut().unwrap();
+        timerfd.set_state(TimerState::Disarmed, SetTimeFlags::Default);
+        let start = Instant::now();
+        use crate::Delay;
+
+        // The delay wakes the task once the missed tick is overdue.
+        let wake = Delay::new(start + period * 3).unwrap();
+        let ticks = (&mut interval).take(2).collect().join(wake);
+        let (ticks, ()) = rt.block_on(ticks).unwrap();
+        assert_eq!(ticks.len(), 2);
+        assert!(start.elapsed() < period * 10, "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn period_fn_spaces_ticks() {
+        let period = |n: u64| Duration::from_millis(10 * (n + 1));
+        let start = Instant::now();
+        let ticks = Interval::with_period_fn(start, period)
+            .unwrap()
+            .take(4)
+            .map(|()| Instant::now());
+        let ticks = current_thread::block_on_all(ticks.collect()).unwrap();
+        let mut nominal = start;
+        for (n, tick) in ticks.into_iter().enumerate() {
+            nominal += period(n as u64);
+            assert!(tick >= nominal, "tick {} early", n);
+            assert!(
+                tick < nominal + Duration::from_millis(20),
+                "tick {} late",
+                n
+            );
+        }
+    }
+
+    #[test]
+    fn period_fn_rejects_zero_period() {
+        let err = Interval::with_period_fn(Instant::now(), |_| Duration::ZERO)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let period = |n| match n {
+            0 => Duration::from_millis(5),
+            _ => Duration::ZERO,
+        };
+        let ticks = Interval::with_period_fn(Instant::now(), period)
+            .unwrap()
+            .collect();
+        let err = current_thread::block_on_all(ticks).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn finalize_returns_pending_expirations() {
+        let period = Duration::from_millis(20);
+        let mut interval = Interval::new_interval(period).unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on((&mut interval).take(1).collect()).unwrap();
+
+        std::thread::sleep(Duration::from_millis(70));
+        // The ticks due by now, counted from the first one not yet read.
+        let next = interval.scheduled;
+        let due = || {
+            let elapsed = Instant::now().saturating_duration_since(next).as_nanos();
+            (elapsed / period.as_nanos() + 1) as u64
+        };
+        let before = due();
+        let pending = interval.finalize().unwrap();
+        let after = due();
+        assert!(
+            pending >= before && pending <= after,
+            "{} {} {}",
+            before,
+            pending,
+            after
+        );
+        assert!(pending >= 2, "{}", pending);
+        let state = interval.timerfd.as_ref().unwrap().get_state();
+        assert_eq!(state, TimerState::Disarmed);
+        assert_eq!(
+            rt.block_on(future::poll_fn(|| interval.poll())).unwrap(),
+            None
+        );
+        assert_eq!(interval.finalize().unwrap(), 0);
+    }
+
+    #[test]
+    fn fps_ticks_on_wall_clock_phase() {
+        let period = Duration::from_nanos(16_666_667);
+        let mut interval = Interval::fps(60.0).unwrap();
+        let frames = (&mut interval).take(3).collect();
+        assert_eq!(current_thread::block_on_all(frames).unwrap().len(), 3);
+
+        let state = interval.timerfd.as_ref().unwrap().get_state();
+        let (current, interval) = match state {
+            TimerState::Periodic { current, interval } => (current, interval),
+            state => panic!("unexpected state {:?}", state),
+        };
+        assert_eq!(interval, period);
+        // The next frame is due on a multiple of the period since the epoch.
+        let next = (SystemTime::now() + current)
+            .duration_since(UNIX_EPOCH)
+            .unwrap();
+        let phase = next.as_nanos() % period.as_nanos();
+        let phase = phase.min(period.as_nanos() - phase);
+        assert!(phase < Duration::from_millis(1).as_nanos(), "{:?}", phase);
+
+        assert!(Interval::fps(0.0).is_err());
+        assert!(Interval::fps(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn primed_interval_polls_without_arming() {
+        let start = Instant::now();
+        let mut interval = Interval::new_interval(Duration::from_millis(10)).unwrap();
+        interval.prime().unwrap();
+        crate::syscalls::take();
+        current_thread::block_on_all((&mut interval).take(2).collect()).unwrap();
+        assert_eq!(crate::syscalls::take().settime, 0);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn pending_polls_skip_timer_state_queries() {
+        let mut interval = Interval::new_interval(Duration::from_secs(60)).unwrap();
+        let polls = future::lazy(|| {
+            for _ in 0..10 {
+                assert!(interval.poll().unwrap().is_not_ready());
+            }
+            Ok::<_, ()>(())
+        });
+        crate::syscalls::take();
+        current_thread::block_on_all(polls).unwrap();
+        assert_eq!(crate::syscalls::take().gettime, 0);
+    }
 }
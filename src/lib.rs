@@ -13,64 +13,351 @@
 //! * [`Interval`] A stream that yields at fixed time intervals.
 //! * [`DelayQueue`]: A queue where items are returned once the requested delay
 //!   has expired.
+//! * [`TimerWheel`]: A set of delays and intervals sharing a single timerfd.
+//! * [`Timeout`]: Wraps a future, failing if it doesn't complete in time.
 //!
 //! [`Delay`]: struct.Delay.html
 //! [`DelayQueue`]: struct.DelayQueue.html
 //! [`Interval`]: struct.Interval.html
+//! [`Timeout`]: struct.Timeout.html
+//! [`TimerWheel`]: struct.TimerWheel.html
 
+use futures::executor::{self, Notify, NotifyHandle};
 use futures::stream::poll_fn;
-use futures::{try_ready, Async, Stream};
+use futures::{future, try_ready, Async, Future, Stream};
 use mio::unix::EventedFd;
 use mio::{Evented, Poll, PollOpt, Ready, Token};
+use std::convert::TryFrom;
 use std::io::Result;
 use std::os::unix::io::AsRawFd;
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use timerfd::{SetTimeFlags, TimerFd as InnerTimerFd, TimerState};
-use tokio_reactor::PollEvented;
+use tokio_reactor::{Handle, PollEvented};
 
 pub use timerfd::ClockId;
 
+mod clock;
+mod countdown;
 mod delay;
 mod delay_queue;
+mod dual_deadline;
+mod error;
 mod interval;
+mod limiter;
+mod multi_rate;
+mod retry_after;
+mod schedule;
+mod timeout;
+mod wheel;
 
-pub use delay::Delay;
+pub use clock::{Clock, ClockDelay, TestClock, TimerFdClock};
+pub use countdown::{countdown, Countdown};
+pub use delay::{
+    global_timer_slack, set_global_timer_slack, CappedDelay, Delay, DelayBuilder, DelayResetHandle,
+    FlagNotifier, OrFlag, SharedDelay,
+};
 pub use delay_queue::DelayQueue;
-pub use interval::Interval;
+pub use dual_deadline::{DualDeadline, Phase};
+pub use error::TimerError;
+pub use interval::{
+    fair_interval, AckInterval, ErrorStream, FairInterval, FairItem, ForwardTo, Gaps, Interval,
+    ScheduleDescriptor, Tick, TickAck, WithStartTick,
+};
+pub use limiter::{AcquireDelay, DelayLimiter, LimitedDelay};
+pub use multi_rate::MultiRateInterval;
+pub use retry_after::{ParseRetryAfterError, RetryAfter};
+pub use schedule::{schedule_once, schedule_periodic, ScheduleHandle, StopHandle};
+pub use timeout::{
+    response_timeout, with_idle_deadline, CatchUnwind, IdleDeadline, ProgressHandle,
+    ResponseTimeout, Timeout, TimeoutError,
+};
+pub use wheel::{TimerInfo, TimerWheel, WheelDelay, WheelInterval};
 
 struct Inner(InnerTimerFd);
 
 impl Evented for Inner {
     fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> Result<()> {
+        #[cfg(test)]
+        syscalls::record(|counts| counts.epoll_ctl += 1);
         poll.register(&EventedFd(&self.0.as_raw_fd()), token, interest, opts)
     }
 
     fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> Result<()> {
+        #[cfg(test)]
+        syscalls::record(|counts| counts.epoll_ctl += 1);
         poll.reregister(&EventedFd(&self.0.as_raw_fd()), token, interest, opts)
     }
 
     fn deregister(&self, poll: &Poll) -> Result<()> {
+        #[cfg(test)]
+        syscalls::record(|counts| counts.epoll_ctl += 1);
         poll.deregister(&EventedFd(&self.0.as_raw_fd()))
     }
 }
 
+impl Inner {
+    /// Arms or disarms the timer, returning its previous state.
+    fn set_state(&mut self, state: TimerState, flags: SetTimeFlags) -> TimerState {
+        #[cfg(test)]
+        syscalls::record(|counts| counts.settime += 1);
+        self.0.set_state(state, flags)
+    }
+
+    fn get_state(&self) -> TimerState {
+        #[cfg(test)]
+        syscalls::record(|counts| counts.gettime += 1);
+        self.0.get_state()
+    }
+
+    /// Reads the number of expirations since the last read, or zero if the
+    /// timer hasn't fired.
+    ///
+    /// Unlike `timerfd::TimerFd::read` this reports read errors instead of
+    /// panicking.
+    fn read(&self) -> Result<u64> {
+        let mut buffer = [0_u8; 8];
+        loop {
+            #[cfg(test)]
+            syscalls::record(|counts| counts.read += 1);
+            match rustix::io::read(&self.0, &mut buffer) {
+                Ok(8) => return Ok(u64::from_ne_bytes(buffer)),
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "short read from timerfd",
+                    ))
+                }
+                Err(rustix::io::Errno::WOULDBLOCK) => return Ok(0),
+                Err(rustix::io::Errno::INTR) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Per-thread counts of the syscalls this crate issues on its timers.
+///
+/// Tests use these to hold timers to the syscall budget documented on `Delay`.
+#[cfg(test)]
+mod syscalls {
+    use std::cell::{Cell, RefCell};
+    use timerfd::{ClockId, TimerFd as InnerTimerFd};
+
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct Counts {
+        pub create: usize,
+        pub settime: usize,
+        pub gettime: usize,
+        pub read: usize,
+        pub epoll_ctl: usize,
+    }
+
+    thread_local!(static COUNTS: Cell<Counts> = Cell::new(Counts::default()));
+    thread_local!(static UNSUPPORTED: RefCell<Vec<ClockId>> = const { RefCell::new(Vec::new()) });
+
+    pub fn record(f: impl FnOnce(&mut Counts)) {
+        COUNTS.with(|counts| {
+            let mut current = counts.get();
+            f(&mut current);
+            counts.set(current);
+        })
+    }
+
+    /// Returns the counts recorded on this thread and resets them.
+    pub fn take() -> Counts {
+        COUNTS.with(|counts| counts.replace(Counts::default()))
+    }
+
+    /// Makes creating timers on `clock` fail on this thread, as on a kernel
+    /// without support for it.
+    pub fn set_unsupported(clock: ClockId) {
+        UNSUPPORTED.with(|clocks| clocks.borrow_mut().push(clock));
+    }
+
+    fn is_unsupported(clock: &ClockId) -> bool {
+        UNSUPPORTED.with(|clocks| clocks.borrow().contains(clock))
+    }
+
+    /// Creates a timerfd, counting the call and failing for clocks marked
+    /// unsupported.
+    pub fn timerfd_create(clock: ClockId, nonblocking: bool) -> std::io::Result<InnerTimerFd> {
+        record(|counts| counts.create += 1);
+        if is_unsupported(&clock) {
+            return Err(std::io::Error::from_raw_os_error(
+                rustix::io::Errno::INVAL.raw_os_error(),
+            ));
+        }
+        InnerTimerFd::new_custom(clock, nonblocking, true)
+    }
+}
+
+#[cfg(test)]
+use syscalls::timerfd_create;
+
+#[cfg(not(test))]
+fn timerfd_create(clock: ClockId, nonblocking: bool) -> Result<InnerTimerFd> {
+    InnerTimerFd::new_custom(clock, nonblocking, true)
+}
+
+/// Creates a timerfd, attaching the clock to any error.
+///
+/// Timers driven by the reactor are non-blocking, so that reading a timer
+/// which hasn't fired reports zero expirations instead of blocking.
+fn create(clock: ClockId, nonblocking: bool) -> Result<InnerTimerFd> {
+    timerfd_create(clock.clone(), nonblocking).map_err(|source| {
+        let kind = source.kind();
+        let source = Arc::new(source);
+        std::io::Error::new(kind, TimerError::ClockInit { clock, source })
+    })
+}
+
 pub struct TimerFd(PollEvented<Inner>);
 
+/// A future resolving with the expirations of a `TimerFd` once it fires.
+///
+/// Created by `TimerFd::next_expiration`.
+pub struct NextExpiration<'a> {
+    timer: &'a mut TimerFd,
+}
+
+impl Future for NextExpiration<'_> {
+    type Item = u64;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Result<Async<u64>> {
+        self.timer.poll_read()
+    }
+}
+
 impl TimerFd {
+    /// Creates a new timer on `clock`, registered with the reactor of the
+    /// current thread once it's first polled.
+    ///
+    /// Creation errors wrap a `TimerError::ClockInit` naming `clock`. They
+    /// keep the `kind()` of the error reported by the kernel, but its errno
+    /// is only available through `TimerError::raw_os_error`.
     pub fn new(clock: ClockId) -> std::io::Result<Self> {
-        let inner = PollEvented::new(Inner(InnerTimerFd::new_custom(clock, true, true)?));
+        let inner = PollEvented::new(Inner(create(clock, true)?));
         Ok(TimerFd(inner))
     }
 
-    fn set_state(&mut self, state: TimerState, flags: SetTimeFlags) {
-        (self.0).get_mut().0.set_state(state, flags);
+    /// Creates a timer that registers with the reactor behind `handle`
+    /// rather than the reactor of the current thread.
+    pub fn new_with_handle(clock: ClockId, handle: &Handle) -> std::io::Result<Self> {
+        let inner = create(clock, true)?;
+        Ok(TimerFd(PollEvented::new_with_handle(Inner(inner), handle)?))
     }
 
-    fn poll_read(&mut self) -> Result<Async<()>> {
+    /// Deregisters the timer from the reactor and returns the underlying
+    /// `timerfd::TimerFd`.
+    ///
+    /// The returned timer is still in non-blocking mode and keeps its current
+    /// state.
+    pub fn into_inner(self) -> Result<InnerTimerFd> {
+        Ok(self.0.into_inner()?.0)
+    }
+
+    /// Deregisters the timer from the reactor and closes its file descriptor.
+    pub fn close(self) -> Result<()> {
+        self.into_inner().map(drop)
+    }
+
+    /// Arms or disarms the timer, returning its previous state.
+    fn set_state(&mut self, state: TimerState, flags: SetTimeFlags) -> TimerState {
+        (self.0).get_mut().set_state(state, flags)
+    }
+
+    /// Arms the timer to expire at the absolute time in `state`.
+    ///
+    /// The kernel fires a timer armed for a time the clock has already
+    /// passed right away, but a zero expiration would disarm the timer and
+    /// some deadlines, such as ones before the epoch, are rejected with
+    /// `EINVAL`. Both are armed to fire right away instead, so a deadline
+    /// that slipped into the past while arming never fails or hangs.
+    fn set_abstime(&mut self, state: TimerState) -> Result<()> {
+        use rustix::time::{Itimerspec, TimerfdTimerFlags, Timespec};
+
+        let fd = &self.0.get_ref().0;
+        let settime = |flags, spec: &Itimerspec| {
+            #[cfg(test)]
+            syscalls::record(|counts| counts.settime += 1);
+            rustix::time::timerfd_settime(fd, flags, spec)
+        };
+        let spec = Itimerspec::from(state);
+        let immediately = Itimerspec {
+            it_interval: spec.it_interval,
+            it_value: Timespec {
+                tv_sec: 0,
+                tv_nsec: 1,
+            },
+        };
+        if spec.it_value.tv_sec == 0 && spec.it_value.tv_nsec == 0 {
+            settime(TimerfdTimerFlags::empty(), &immediately)?;
+            return Ok(());
+        }
+        match settime(TimerfdTimerFlags::ABSTIME, &spec) {
+            Ok(_) => Ok(()),
+            Err(rustix::io::Errno::INVAL) => {
+                settime(TimerfdTimerFlags::empty(), &immediately)?;
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn get_state(&self) -> TimerState {
+        (self.0).get_ref().get_state()
+    }
+
+    /// Polls for the timer to fire, returning the number of expirations
+    /// since the last read.
+    ///
+    /// Errors reported by the reactor, such as failing to register the fd,
+    /// are returned as well as errors reading the timer, so a broken timer
+    /// fails its future instead of leaving it pending forever.
+    fn poll_read(&mut self) -> Result<Async<u64>> {
         let ready = try_ready!(self.0.poll_read_ready(Ready::readable()));
-        self.0.get_mut().0.read();
+        let expirations = self.0.get_ref().read()?;
         self.0.clear_read_ready(ready)?;
-        Ok(Async::Ready(()))
+        // Readiness may be stale if the timer was re-armed after it fired.
+        if expirations == 0 {
+            return Ok(Async::NotReady);
+        }
+        Ok(Async::Ready(expirations))
+    }
+
+    /// Registers the timer with the reactor without waiting for it to fire.
+    ///
+    /// Registration normally happens on the first poll. This does it from
+    /// outside of a task, using a task that ignores notifications; the next
+    /// real poll replaces it and still sees readiness reported meanwhile.
+    fn register(&self) -> Result<()> {
+        struct Ignore;
+
+        impl Notify for Ignore {
+            fn notify(&self, _id: usize) {}
+        }
+
+        let ready = future::poll_fn(|| self.0.poll_read_ready(Ready::readable()));
+        executor::spawn(ready).poll_future_notify(&NotifyHandle::from(Arc::new(Ignore)), 0)?;
+        Ok(())
+    }
+
+    /// Reads the number of expirations without waiting for the reactor to
+    /// report readiness. Returns zero if the timer hasn't fired.
+    fn read_now(&mut self) -> Result<u64> {
+        self.0.get_ref().read()
+    }
+
+    /// Returns a future resolving with the number of expirations once the
+    /// timer next fires.
+    ///
+    /// This works the same for oneshot and periodic timers. The expirations
+    /// are consumed when the future resolves, so calling this again after a
+    /// periodic timer fired waits for the following expiration.
+    pub fn next_expiration(&mut self) -> NextExpiration<'_> {
+        NextExpiration { timer: self }
     }
 
     #[deprecated(note = "please use Interval")]
@@ -89,18 +376,66 @@ impl TimerFd {
     }
 }
 
+/// Converts a nanosecond count that may exceed `u64` into a `Duration`,
+/// saturating at `Duration::MAX`.
+fn duration_from_nanos(nanos: u128) -> Duration {
+    match u64::try_from(nanos / 1_000_000_000) {
+        Ok(secs) => Duration::new(secs, (nanos % 1_000_000_000) as u32),
+        Err(_) => Duration::MAX,
+    }
+}
+
+/// Translates a wall-clock time into the corresponding monotonic instant.
+fn instant_from_system_time(time: SystemTime) -> Instant {
+    let (now, sys_now) = (Instant::now(), SystemTime::now());
+    match time.duration_since(sys_now) {
+        Ok(ahead) => now + ahead,
+        Err(err) => now.checked_sub(err.duration()).unwrap_or(now),
+    }
+}
+
+/// Translates a monotonic instant into the corresponding wall-clock time.
+fn system_time_from_instant(at: Instant) -> SystemTime {
+    let (now, sys_now) = (Instant::now(), SystemTime::now());
+    if at > now {
+        sys_now + (at - now)
+    } else {
+        sys_now - (now - at)
+    }
+}
+
 /// Create a Future that completes in `duration` from now.
 pub fn sleep(duration: Duration) -> Delay {
     Delay::new(Instant::now() + duration).expect("can't create delay")
 }
 
+/// Returns the nominal resolution of the clock backing [`Delay`] and [`Interval`].
+///
+/// The value is obtained from `clock_getres(2)` for `CLOCK_MONOTONIC`, which on
+/// Linux with high-resolution timers is 1 nanosecond. This is the granularity
+/// the kernel accepts when arming a timer, not a promise about wakeup latency:
+/// the achievable resolution is bounded by scheduling and reactor overhead and
+/// is usually in the tens of microseconds.
+///
+/// The crate doesn't measure the achievable resolution, so there is no
+/// `measured_resolution()` to compare against. `Delay::calibrate` measures
+/// the overshoot of a single delay on the current machine instead.
+///
+/// [`Delay`]: struct.Delay.html
+/// [`Interval`]: struct.Interval.html
+pub fn resolution_contract() -> Duration {
+    let res = rustix::time::clock_getres(rustix::time::ClockId::Monotonic);
+    Duration::new(res.tv_sec as u64, res.tv_nsec as u32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::time::Instant;
-    use tokio::prelude::*;
+    use tokio::runtime::current_thread;
 
     #[test]
+    #[allow(deprecated)]
     fn periodic_works() {
         let timer = TimerFd::new(ClockId::Monotonic).unwrap();
         tokio::run(future::lazy(|| {
@@ -118,4 +453,130 @@ mod tests {
                 })
         }));
     }
+
+    #[test]
+    fn into_inner_returns_usable_timer() {
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let mut timer = TimerFd::new(ClockId::Monotonic).unwrap();
+        let registered = rt
+            .block_on(future::lazy(move || {
+                assert!(timer.poll_read().unwrap().is_not_ready());
+                Ok::<_, ()>(timer)
+            }))
+            .unwrap();
+        let mut inner = registered.into_inner().unwrap();
+        inner.set_state(
+            TimerState::Oneshot(Duration::from_millis(1)),
+            SetTimeFlags::Default,
+        );
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(inner.read(), 1);
+    }
+
+    #[test]
+    fn close_releases_fd() {
+        let timer = TimerFd::new(ClockId::Monotonic).unwrap();
+        let path = format!("/proc/self/fd/{}", timer.0.get_ref().0.as_raw_fd());
+        assert!(std::fs::read_link(&path).is_ok());
+        timer.close().unwrap();
+        assert!(std::fs::read_link(&path).is_err());
+    }
+
+    #[test]
+    fn reactor_errors_fail_poll() {
+        use std::mem::ManuallyDrop;
+        use std::os::unix::io::{FromRawFd, OwnedFd};
+
+        let mut timer = TimerFd::new(ClockId::Monotonic).unwrap();
+        timer.set_state(
+            TimerState::Oneshot(Duration::from_millis(1)),
+            SetTimeFlags::Default,
+        );
+        // Swap a directory in under the timer's fd number. epoll refuses to
+        // watch it, so registering with the reactor fails.
+        let dir = std::fs::File::open("/").unwrap();
+        let raw = timer.0.get_ref().0.as_raw_fd();
+        let mut fd = ManuallyDrop::new(unsafe { OwnedFd::from_raw_fd(raw) });
+        rustix::io::dup2(&dir, &mut fd).unwrap();
+        let poll = future::poll_fn(move || timer.poll_read());
+        assert!(current_thread::block_on_all(poll).is_err());
+    }
+
+    #[test]
+    fn past_abstime_fires_immediately() {
+        use std::time::UNIX_EPOCH;
+
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let past = since_epoch - Duration::from_millis(5);
+        for state in [
+            TimerState::Oneshot(past),
+            TimerState::Oneshot(Duration::ZERO),
+        ] {
+            let mut timer = TimerFd::new(ClockId::Realtime).unwrap();
+            timer.set_abstime(state).unwrap();
+            let now = Instant::now();
+            let poll = future::poll_fn(move || timer.poll_read());
+            assert_eq!(current_thread::block_on_all(poll).unwrap(), 1);
+            assert!(now.elapsed() < Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn creation_error_keeps_errno_reachable() {
+        syscalls::set_unsupported(ClockId::RealtimeAlarm);
+        let err = match TimerFd::new(ClockId::RealtimeAlarm) {
+            Ok(_) => panic!("timer created on an unsupported clock"),
+            Err(err) => err,
+        };
+        let einval = rustix::io::Errno::INVAL.raw_os_error();
+        assert_eq!(err.kind(), std::io::Error::from_raw_os_error(einval).kind());
+        assert_eq!(err.raw_os_error(), None);
+        let source = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<TimerError>());
+        assert_eq!(source.and_then(TimerError::raw_os_error), Some(einval));
+    }
+
+    #[test]
+    fn duration_from_nanos_saturates() {
+        assert_eq!(
+            duration_from_nanos(1_500_000_000),
+            Duration::new(1, 500_000_000)
+        );
+        assert_eq!(duration_from_nanos(u128::MAX), Duration::MAX);
+    }
+
+    #[test]
+    fn resolution_contract_is_sub_millisecond() {
+        let res = resolution_contract();
+        assert!(res > Duration::from_nanos(0));
+        assert!(res < Duration::from_millis(1));
+    }
+
+    #[test]
+    fn next_expiration_of_oneshot_and_periodic() {
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let mut oneshot = TimerFd::new(ClockId::Monotonic).unwrap();
+        let state = TimerState::Oneshot(Duration::from_millis(5));
+        oneshot.set_state(state, SetTimeFlags::Default);
+        assert_eq!(rt.block_on(oneshot.next_expiration()).unwrap(), 1);
+
+        let period = Duration::from_millis(10);
+        let mut periodic = TimerFd::new(ClockId::Monotonic).unwrap();
+        let state = TimerState::Periodic {
+            current: period,
+            interval: period,
+        };
+        periodic.set_state(state, SetTimeFlags::Default);
+        let start = Instant::now();
+        assert!(rt.block_on(periodic.next_expiration()).unwrap() >= 1);
+        let first = start.elapsed();
+        assert!(rt.block_on(periodic.next_expiration()).unwrap() >= 1);
+        // The second call waited for the next expiration.
+        assert!(
+            start.elapsed() >= first + period / 2,
+            "{:?}",
+            start.elapsed()
+        );
+    }
 }
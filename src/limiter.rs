@@ -0,0 +1,181 @@
+use crate::Delay;
+use futures::{task, Async, Future, Poll};
+use std::io::Error as IoError;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+struct Inner {
+    available: usize,
+    waiters: Vec<task::Task>,
+}
+
+/// Caps the number of delays, and thus timer fds, alive at once.
+///
+/// `DelayLimiter` is a cheap handle, clones share the same limit.
+#[derive(Clone)]
+pub struct DelayLimiter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl DelayLimiter {
+    /// Create a new `DelayLimiter` allowing `max` delays at once.
+    pub fn new(max: usize) -> DelayLimiter {
+        DelayLimiter {
+            inner: Arc::new(Mutex::new(Inner {
+                available: max,
+                waiters: Vec::new(),
+            })),
+        }
+    }
+
+    /// Returns a future resolving to a `Delay` that elapses at `deadline`.
+    ///
+    /// The future resolves right away if fewer than `max` delays created by
+    /// this limiter are alive, and otherwise once one of them is dropped.
+    pub fn acquire_delay(&self, deadline: Instant) -> AcquireDelay {
+        AcquireDelay {
+            limiter: self.clone(),
+            deadline,
+        }
+    }
+
+    fn release(&self) {
+        let waiters = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.available += 1;
+            mem::take(&mut inner.waiters)
+        };
+        // Waiters that lose the race for the slot register again.
+        for waiter in waiters {
+            waiter.notify();
+        }
+    }
+}
+
+/// A future waiting for a slot of a `DelayLimiter`.
+///
+/// Created by `DelayLimiter::acquire_delay`.
+pub struct AcquireDelay {
+    limiter: DelayLimiter,
+    deadline: Instant,
+}
+
+impl Future for AcquireDelay {
+    type Item = LimitedDelay;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let mut inner = self.limiter.inner.lock().unwrap();
+            if inner.available == 0 {
+                // A task polling again is already woken by the next release.
+                if !inner.waiters.iter().any(|task| task.will_notify_current()) {
+                    inner.waiters.push(task::current());
+                }
+                return Ok(Async::NotReady);
+            }
+            inner.available -= 1;
+        }
+        let permit = Permit(self.limiter.clone());
+        let delay = Delay::new(self.deadline)?;
+        Ok(Async::Ready(LimitedDelay {
+            delay,
+            _permit: permit,
+        }))
+    }
+}
+
+/// Releases a slot of a `DelayLimiter` on drop.
+struct Permit(DelayLimiter);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// A `Delay` holding a slot of a `DelayLimiter` until it is dropped.
+pub struct LimitedDelay {
+    delay: Delay,
+    _permit: Permit,
+}
+
+impl LimitedDelay {
+    /// Returns the instant at which the future will complete.
+    pub fn deadline(&self) -> Instant {
+        self.delay.deadline()
+    }
+}
+
+impl Future for LimitedDelay {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.delay.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::runtime::current_thread;
+
+    #[test]
+    fn third_delay_waits_for_a_free_slot() {
+        let limiter = DelayLimiter::new(2);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let result = current_thread::block_on_all(futures::future::lazy(move || {
+            let first = limiter.acquire_delay(deadline).poll()?;
+            let second = limiter.acquire_delay(deadline).poll()?;
+            let mut third = limiter.acquire_delay(deadline);
+            assert!(first.is_ready() && second.is_ready());
+            assert!(third.poll()?.is_not_ready());
+            drop(first);
+            assert!(third.poll()?.is_ready());
+            Ok::<_, IoError>(())
+        }));
+        result.unwrap();
+    }
+
+    #[test]
+    fn repeated_polls_register_one_waiter() {
+        let limiter = DelayLimiter::new(1);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let result = current_thread::block_on_all(futures::future::lazy(move || {
+            let _held = limiter.acquire_delay(deadline).poll()?;
+            let mut waiting = limiter.acquire_delay(deadline);
+            for _ in 0..3 {
+                assert!(waiting.poll()?.is_not_ready());
+            }
+            assert_eq!(limiter.inner.lock().unwrap().waiters.len(), 1);
+            Ok::<_, IoError>(())
+        }));
+        result.unwrap();
+    }
+
+    #[test]
+    fn released_slot_wakes_waiting_task() {
+        let limiter = DelayLimiter::new(1);
+        let start = Instant::now();
+        let second = futures::future::lazy(move || {
+            // Another task holds the only slot until its delay elapses.
+            let first = match limiter
+                .acquire_delay(start + Duration::from_millis(20))
+                .poll()?
+            {
+                Async::Ready(first) => first,
+                Async::NotReady => panic!("no free slot"),
+            };
+            tokio::spawn(first.map_err(|err| panic!("{:?}", err)));
+            let mut second = limiter.acquire_delay(start);
+            assert!(second.poll()?.is_not_ready());
+            Ok::<_, IoError>(second)
+        })
+        .flatten();
+        current_thread::block_on_all(second).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{duration_from_nanos, ClockId, TimerFd};
+use futures::{try_ready, Async, Stream};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{Error as IoError, ErrorKind};
+use std::time::{Duration, Instant};
+use timerfd::{SetTimeFlags, TimerState};
+
+/// A stream multiplexing several periodic schedules onto one timerfd.
+///
+/// Each label ticks at its own period, starting one period after the stream
+/// is created, and the stream yields `(label, instant)` for every tick, where
+/// `instant` is the time the tick was scheduled for. The timerfd is armed for
+/// the nearest tick across all labels. As with `Interval`, ticks missed by a
+/// slow consumer are coalesced and each schedule keeps its phase.
+pub struct MultiRateInterval<L> {
+    timerfd: TimerFd,
+    labels: Vec<(L, Duration)>,
+    /// Next tick of every label, by index into `labels`.
+    queue: BinaryHeap<Reverse<(Instant, usize)>>,
+    armed: Option<Instant>,
+}
+
+impl<L: Clone> MultiRateInterval<L> {
+    /// Create a new `MultiRateInterval` ticking each label at its period.
+    ///
+    /// An empty set of labels would never tick, and is rejected with
+    /// `ErrorKind::InvalidInput`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if any period is zero.
+    pub fn new<I>(periods: I) -> Result<MultiRateInterval<L>, IoError>
+    where
+        I: IntoIterator<Item = (L, Duration)>,
+    {
+        let labels: Vec<_> = periods.into_iter().collect();
+        if labels.is_empty() {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "multi-rate interval needs at least one label",
+            ));
+        }
+        let timerfd = TimerFd::new(ClockId::Monotonic)?;
+        let now = Instant::now();
+        let queue = labels
+            .iter()
+            .enumerate()
+            .map(|(index, (_, period))| {
+                assert!(!period.is_zero(), "`period` must be non-zero.");
+                Reverse((now + *period, index))
+            })
+            .collect();
+        Ok(MultiRateInterval {
+            timerfd,
+            labels,
+            queue,
+            armed: None,
+        })
+    }
+
+    fn arm(&mut self, deadline: Instant, now: Instant) {
+        if self.armed == Some(deadline) {
+            return;
+        }
+        // A zero duration would disarm the timer.
+        let duration = (deadline - now).max(Duration::from_nanos(1));
+        self.timerfd
+            .set_state(TimerState::Oneshot(duration), SetTimeFlags::Default);
+        self.armed = Some(deadline);
+    }
+}
+
+impl<L: Clone> Stream for MultiRateInterval<L> {
+    type Item = (L, Instant);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        loop {
+            let Reverse((deadline, index)) = match self.queue.peek() {
+                Some(next) => *next,
+                None => return Ok(Async::NotReady),
+            };
+            let now = Instant::now();
+            if deadline <= now {
+                self.queue.pop();
+                let (label, period) = &self.labels[index];
+                let periods = (now - deadline).as_nanos() / period.as_nanos() + 1;
+                let next = deadline + duration_from_nanos(period.as_nanos() * periods);
+                self.queue.push(Reverse((next, index)));
+                return Ok(Async::Ready(Some((label.clone(), deadline))));
+            }
+            self.arm(deadline, now);
+            try_ready!(self.timerfd.poll_read());
+            self.armed = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::current_thread;
+
+    #[test]
+    fn labels_tick_at_their_own_rate() {
+        let start = Instant::now();
+        let interval = MultiRateInterval::new(vec![
+            ("fast", Duration::from_millis(10)),
+            ("mid", Duration::from_millis(20)),
+            ("slow", Duration::from_millis(40)),
+        ])
+        .unwrap();
+        let end = start + Duration::from_millis(85);
+        let ticks = interval.take_while(move |(_, at)| Ok(*at < end)).collect();
+        let ticks = current_thread::block_on_all(ticks).unwrap();
+        assert!(Instant::now() >= start + Duration::from_millis(80));
+
+        let of = |name| -> Vec<Instant> {
+            let ticks = ticks.iter().filter(|(label, _)| *label == name);
+            ticks.map(|(_, at)| *at).collect()
+        };
+        let (fast, mid, slow) = (of("fast"), of("mid"), of("slow"));
+        // A tick delayed past the next one on a loaded machine is coalesced.
+        assert!(fast.len() >= 6 && fast.len() <= 8, "{}", fast.len());
+        assert!(mid.len() >= 3 && mid.len() <= 4, "{}", mid.len());
+        assert_eq!(slow.len(), 2);
+        for (ticks, period) in [(fast, 10), (mid, 20), (slow, 40)].iter() {
+            for pair in ticks.windows(2) {
+                let gap = (pair[1] - pair[0]).as_nanos();
+                assert_eq!(gap % Duration::from_millis(*period).as_nanos(), 0);
+            }
+        }
+        for pair in ticks.windows(2) {
+            assert!(pair[0].1 <= pair[1].1);
+        }
+    }
+
+    #[test]
+    fn empty_label_set_is_rejected() {
+        let err = MultiRateInterval::<&str>::new(Vec::new()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
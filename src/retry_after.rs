@@ -0,0 +1,161 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The value of an HTTP `Retry-After` header.
+///
+/// Use [`Delay::from_retry_after`] to wait for it.
+///
+/// [`Delay::from_retry_after`]: struct.Delay.html#method.from_retry_after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryAfter {
+    /// Retry after the given delay.
+    Delay(Duration),
+    /// Retry at the given wall-clock time.
+    Date(SystemTime),
+}
+
+/// Error returned when a `Retry-After` value can't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRetryAfterError(());
+
+impl fmt::Display for ParseRetryAfterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid Retry-After value")
+    }
+}
+
+impl Error for ParseRetryAfterError {}
+
+impl FromStr for RetryAfter {
+    type Err = ParseRetryAfterError;
+
+    /// Parses either delta-seconds or an HTTP date in the IMF-fixdate format,
+    /// e.g. `Sun, 06 Nov 1994 08:49:37 GMT`. The obsolete RFC 850 and asctime
+    /// date formats are not supported.
+    fn from_str(s: &str) -> Result<RetryAfter, ParseRetryAfterError> {
+        let s = s.trim();
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            let secs = s.parse().map_err(|_| ParseRetryAfterError(()))?;
+            return Ok(RetryAfter::Delay(Duration::from_secs(secs)));
+        }
+        parse_imf_fixdate(s)
+            .map(RetryAfter::Date)
+            .ok_or(ParseRetryAfterError(()))
+    }
+}
+
+fn parse_imf_fixdate(s: &str) -> Option<SystemTime> {
+    const WEEKDAYS: [&str; 7] = ["Mon,", "Tue,", "Wed,", "Thu,", "Fri,", "Sat,", "Sun,"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let parts: Vec<&str> = s.split(' ').collect();
+    if parts.len() != 6 || !WEEKDAYS.contains(&parts[0]) || parts[5] != "GMT" {
+        return None;
+    }
+    let day: u64 = parse_digits(parts[1], 2)?;
+    let month = MONTHS.iter().position(|&m| m == parts[2])? as u64 + 1;
+    let year: u64 = parse_digits(parts[3], 4)?;
+    let time: Vec<&str> = parts[4].split(':').collect();
+    if time.len() != 3 {
+        return None;
+    }
+    let hour: u64 = parse_digits(time[0], 2)?;
+    let minute: u64 = parse_digits(time[1], 2)?;
+    let second: u64 = parse_digits(time[2], 2)?;
+    if year < 1970 || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let days = days_since_epoch(year, month, day);
+    let secs = days * 86_400 + hour * 3_600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn parse_digits(s: &str, len: usize) -> Option<u64> {
+    if s.len() != len || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Number of days in `month` (1-based) of `year`.
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Number of days from 1970-01-01 to the given date in the proleptic
+/// Gregorian calendar.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    // Shift the year to start in March so the leap day is the last one.
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_delay_seconds() {
+        assert_eq!(
+            "120".parse(),
+            Ok(RetryAfter::Delay(Duration::from_secs(120)))
+        );
+    }
+
+    #[test]
+    fn parse_http_date() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(
+            "Sun, 06 Nov 1994 08:49:37 GMT".parse(),
+            Ok(RetryAfter::Date(date))
+        );
+        let leap = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(
+            "Tue, 29 Feb 2000 00:00:00 GMT".parse(),
+            Ok(RetryAfter::Date(leap))
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!("".parse::<RetryAfter>().is_err());
+        assert!("-5".parse::<RetryAfter>().is_err());
+        assert!("Sunday, 06-Nov-94 08:49:37 GMT"
+            .parse::<RetryAfter>()
+            .is_err());
+        // Days past the end of the month.
+        assert!("Sun, 31 Feb 2021 00:00:00 GMT"
+            .parse::<RetryAfter>()
+            .is_err());
+        assert!("Thu, 29 Feb 2001 00:00:00 GMT"
+            .parse::<RetryAfter>()
+            .is_err());
+        assert!("Fri, 31 Apr 2020 00:00:00 GMT"
+            .parse::<RetryAfter>()
+            .is_err());
+        assert!("Mon, 29 Feb 2100 00:00:00 GMT"
+            .parse::<RetryAfter>()
+            .is_err());
+    }
+}
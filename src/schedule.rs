@@ -0,0 +1,199 @@
+use crate::{Delay, FlagNotifier, Interval};
+use futures::future::poll_fn;
+use futures::task::AtomicTask;
+use futures::{Async, Future, Stream};
+use log::warn;
+use std::io::Error as IoError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Handle to a closure scheduled with [`schedule_once`].
+///
+/// Dropping the handle doesn't cancel the closure.
+///
+/// [`schedule_once`]: fn.schedule_once.html
+#[derive(Clone)]
+pub struct ScheduleHandle {
+    notifier: FlagNotifier,
+}
+
+impl ScheduleHandle {
+    /// Cancel the scheduled closure.
+    ///
+    /// If the delay hasn't fired yet the closure never runs and the task
+    /// awaiting the delay ends right away.
+    pub fn cancel(&self) {
+        self.notifier.set();
+    }
+}
+
+/// Run `f` once, `delay` from now, on a task spawned on the default executor.
+///
+/// Returns a handle that can cancel the closure before it runs.
+///
+/// # Panics
+///
+/// This function panics if there is no default executor, e.g. when called
+/// from outside a runtime.
+pub fn schedule_once<F>(delay: Duration, f: F) -> Result<ScheduleHandle, IoError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let delay = Delay::or_flag(Instant::now() + delay, Arc::new(AtomicBool::new(false)))?;
+    let handle = ScheduleHandle {
+        notifier: delay.notifier(),
+    };
+    tokio_executor::spawn(delay.then(|result| {
+        match result {
+            Ok(false) => f(),
+            Ok(true) => {}
+            Err(err) => warn!("scheduled closure dropped: {}", err),
+        }
+        Ok(())
+    }));
+    Ok(handle)
+}
+
+/// Handle to a closure scheduled with [`schedule_periodic`].
+///
+/// Dropping the handle doesn't stop the closure.
+///
+/// [`schedule_periodic`]: fn.schedule_periodic.html
+#[derive(Clone)]
+pub struct StopHandle {
+    stopped: Arc<AtomicBool>,
+    task: Arc<AtomicTask>,
+}
+
+impl StopHandle {
+    /// Stop calling the scheduled closure.
+    ///
+    /// The task driving the interval is woken and ends, releasing its timer.
+    /// Stopping more than once has no further effect.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.task.notify();
+    }
+}
+
+/// Call `f` every `period`, starting `period` from now, on a task spawned
+/// on the default executor.
+///
+/// Ticks are driven by an `Interval`, so a slow closure causes missed ticks
+/// to be coalesced rather than queued. Returns a handle that stops the
+/// schedule.
+///
+/// # Panics
+///
+/// This function panics if `period` is zero or if there is no default
+/// executor, e.g. when called from outside a runtime.
+pub fn schedule_periodic<F>(period: Duration, mut f: F) -> Result<StopHandle, IoError>
+where
+    F: FnMut() + Send + 'static,
+{
+    let mut interval = Interval::new_interval(period)?;
+    let handle = StopHandle {
+        stopped: Arc::new(AtomicBool::new(false)),
+        task: Arc::new(AtomicTask::new()),
+    };
+    let stop = handle.clone();
+    tokio_executor::spawn(poll_fn(move || loop {
+        // Register before checking the flag so a concurrent `stop` can't be
+        // missed.
+        stop.task.register();
+        if stop.stopped.load(Ordering::SeqCst) {
+            return Ok(Async::Ready(()));
+        }
+        match interval.poll() {
+            Ok(Async::Ready(Some(()))) => f(),
+            Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(err) => {
+                warn!("periodic closure stopped: {}", err);
+                return Ok(Async::Ready(()));
+            }
+        }
+    }));
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn schedule_once_runs_after_delay() {
+        let mut rt = Runtime::new().unwrap();
+        let start = Instant::now();
+        let ran_at = Arc::new(Mutex::new(None));
+        let slot = ran_at.clone();
+        rt.block_on(futures::future::lazy(|| {
+            schedule_once(Duration::from_millis(20), move || {
+                *slot.lock().unwrap() = Some(Instant::now())
+            })
+        }))
+        .unwrap();
+        rt.run().unwrap();
+        let ran_at = ran_at.lock().unwrap().expect("closure didn't run");
+        assert!(ran_at - start >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn cancelled_schedule_doesnt_run() {
+        let mut rt = Runtime::new().unwrap();
+        let start = Instant::now();
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = ran.clone();
+        let handle = rt
+            .block_on(futures::future::lazy(|| {
+                schedule_once(Duration::from_millis(500), move || {
+                    flag.store(true, Ordering::SeqCst)
+                })
+            }))
+            .unwrap();
+        handle.cancel();
+        rt.run().unwrap();
+        assert!(!ran.load(Ordering::SeqCst));
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn stopped_periodic_schedule_halts() {
+        let period = Duration::from_millis(10);
+        let mut rt = Runtime::new().unwrap();
+        let calls = Arc::new(Mutex::new(0));
+        let counter = calls.clone();
+        let handle = rt
+            .block_on(futures::future::lazy(|| {
+                schedule_periodic(period, move || *counter.lock().unwrap() += 1)
+            }))
+            .unwrap();
+        let calls_at_stop = Arc::new(Mutex::new(0));
+        let (calls_seen, stop) = (calls.clone(), calls_at_stop.clone());
+        let stop_after = period * 5 + period / 2;
+        let stopper = Delay::new(Instant::now() + stop_after)
+            .unwrap()
+            .map(move |_| {
+                handle.stop();
+                handle.stop();
+                *stop.lock().unwrap() = *calls_seen.lock().unwrap();
+            })
+            .map_err(|err| panic!("{:?}", err));
+        let start = Instant::now();
+        rt.spawn(stopper);
+        rt.run().unwrap();
+        // The runtime only exits once the stopped timer is dropped, the bound
+        // merely leaves room for a loaded machine.
+        assert!(
+            start.elapsed() < stop_after + Duration::from_millis(200),
+            "{:?}",
+            start.elapsed()
+        );
+        let calls_at_stop = *calls_at_stop.lock().unwrap();
+        assert!(calls_at_stop >= 3, "{}", calls_at_stop);
+        assert_eq!(*calls.lock().unwrap(), calls_at_stop);
+    }
+}
@@ -0,0 +1,430 @@
+use crate::Delay;
+use futures::{Async, Future, Poll, Stream};
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io::Error as IoError;
+use std::panic::{self, AssertUnwindSafe, UnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Error returned by timeout combinators.
+#[derive(Debug)]
+pub enum TimeoutError<E> {
+    /// The inner future or stream failed.
+    Inner(E),
+    /// The deadline was reached before the inner future completed.
+    Elapsed,
+    /// The timer backing the deadline failed.
+    Timer(IoError),
+    /// The inner future panicked, carrying the panic payload.
+    ///
+    /// Only returned by futures created with `Timeout::catch_unwind`.
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl<E: fmt::Display> fmt::Display for TimeoutError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeoutError::Inner(err) => write!(f, "inner error: {}", err),
+            TimeoutError::Elapsed => write!(f, "deadline has elapsed"),
+            TimeoutError::Timer(err) => write!(f, "timer error: {}", err),
+            TimeoutError::Panicked(_) => write!(f, "inner future panicked"),
+        }
+    }
+}
+
+impl<E: Error> Error for TimeoutError<E> {}
+
+/// A future that fails if its inner future doesn't complete by a deadline.
+pub struct Timeout<F> {
+    future: F,
+    delay: Delay,
+}
+
+impl<F: Future> Timeout<F> {
+    /// Require `future` to complete within `timeout` from now.
+    pub fn new(future: F, timeout: Duration) -> Result<Timeout<F>, IoError> {
+        Timeout::new_at(future, Instant::now() + timeout)
+    }
+
+    /// Require `future` to complete by `deadline`.
+    pub fn new_at(future: F, deadline: Instant) -> Result<Timeout<F>, IoError> {
+        Ok(Timeout {
+            future,
+            delay: Delay::new(deadline)?,
+        })
+    }
+
+    /// Catch panics of the inner future.
+    ///
+    /// A panic while polling the inner future is returned as
+    /// `TimeoutError::Panicked` instead of unwinding through the caller. The
+    /// inner future is dropped right away, so the returned future must not be
+    /// polled again after it fails this way.
+    pub fn catch_unwind(self) -> CatchUnwind<F>
+    where
+        F: UnwindSafe,
+    {
+        CatchUnwind { inner: Some(self) }
+    }
+
+    fn poll_delay(&mut self) -> Poll<F::Item, TimeoutError<F::Error>> {
+        match self.delay.poll() {
+            Ok(Async::Ready(())) => Err(TimeoutError::Elapsed),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => Err(TimeoutError::Timer(err)),
+        }
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Item = F::Item;
+    type Error = TimeoutError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.future.poll() {
+            Ok(Async::Ready(item)) => return Ok(Async::Ready(item)),
+            Ok(Async::NotReady) => {}
+            Err(err) => return Err(TimeoutError::Inner(err)),
+        }
+        self.poll_delay()
+    }
+}
+
+/// A `Timeout` that reports panics of its inner future as errors.
+///
+/// Created by `Timeout::catch_unwind`.
+pub struct CatchUnwind<F> {
+    inner: Option<Timeout<F>>,
+}
+
+impl<F: Future + UnwindSafe> Future for CatchUnwind<F> {
+    type Item = F::Item;
+    type Error = TimeoutError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = self
+            .inner
+            .as_mut()
+            .expect("`CatchUnwind` polled after it panicked");
+        let future = &mut inner.future;
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll())) {
+            Ok(Ok(Async::Ready(item))) => return Ok(Async::Ready(item)),
+            Ok(Ok(Async::NotReady)) => {}
+            Ok(Err(err)) => return Err(TimeoutError::Inner(err)),
+            Err(payload) => {
+                self.inner = None;
+                return Err(TimeoutError::Panicked(payload));
+            }
+        }
+        inner.poll_delay()
+    }
+}
+
+/// Handle used to report progress of an operation guarded by
+/// [`with_idle_deadline`].
+///
+/// Handles are cheap to clone and can be moved to whatever code observes the
+/// progress of the operation.
+///
+/// [`with_idle_deadline`]: fn.with_idle_deadline.html
+#[derive(Debug, Clone, Default)]
+pub struct ProgressHandle {
+    last_touch: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ProgressHandle {
+    /// Create a new `ProgressHandle`.
+    pub fn new() -> ProgressHandle {
+        ProgressHandle::default()
+    }
+
+    /// Report progress, moving the idle deadline to `idle` from now.
+    pub fn touch(&self) {
+        *self.last_touch.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn take_last_touch(&self) -> Option<Instant> {
+        self.last_touch.lock().unwrap().take()
+    }
+}
+
+/// A future that fails if its inner future makes no progress for too long.
+///
+/// Created by [`with_idle_deadline`].
+///
+/// [`with_idle_deadline`]: fn.with_idle_deadline.html
+pub struct IdleDeadline<F> {
+    future: F,
+    delay: Delay,
+    idle: Duration,
+    progress: ProgressHandle,
+}
+
+/// Require `future` to complete without going `idle` between progress reports.
+///
+/// Every call to `progress.touch()` moves the deadline to `idle` after the
+/// touch. The returned future fails with `TimeoutError::Elapsed` only if
+/// `idle` passes without any progress.
+pub fn with_idle_deadline<F: Future>(
+    future: F,
+    idle: Duration,
+    progress: &ProgressHandle,
+) -> Result<IdleDeadline<F>, IoError> {
+    let delay = Delay::new(Instant::now() + idle)?;
+    Ok(IdleDeadline {
+        future,
+        delay,
+        idle,
+        progress: progress.clone(),
+    })
+}
+
+impl<F: Future> Future for IdleDeadline<F> {
+    type Item = F::Item;
+    type Error = TimeoutError<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.future.poll() {
+            Ok(Async::Ready(item)) => return Ok(Async::Ready(item)),
+            Ok(Async::NotReady) => {}
+            Err(err) => return Err(TimeoutError::Inner(err)),
+        }
+        if let Some(touched_at) = self.progress.take_last_touch() {
+            self.delay.reset(touched_at + self.idle);
+        }
+        match self.delay.poll() {
+            Ok(Async::Ready(())) => Err(TimeoutError::Elapsed),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => Err(TimeoutError::Timer(err)),
+        }
+    }
+}
+
+/// A stream that fails if a response doesn't follow a request in time.
+///
+/// Created by [`response_timeout`].
+///
+/// [`response_timeout`]: fn.response_timeout.html
+pub struct ResponseTimeout<S, Q, R> {
+    stream: S,
+    delay: Delay,
+    timeout: Duration,
+    pending: bool,
+    is_request: Q,
+    is_response: R,
+}
+
+/// Require every request passing through `stream` to be followed by a
+/// response within `timeout`.
+///
+/// Items are passed through unchanged. An item for which `is_request`
+/// returns true starts the timer, unless one is already running, and the
+/// next item for which `is_response` returns true stops it. If the timer
+/// fires first the stream fails with `TimeoutError::Elapsed`.
+///
+/// The timerfd is only created once the first request is seen.
+pub fn response_timeout<S, Q, R>(
+    stream: S,
+    timeout: Duration,
+    is_request: Q,
+    is_response: R,
+) -> ResponseTimeout<S, Q, R>
+where
+    S: Stream,
+    Q: FnMut(&S::Item) -> bool,
+    R: FnMut(&S::Item) -> bool,
+{
+    ResponseTimeout {
+        stream,
+        delay: Delay::lazy(Instant::now()),
+        timeout,
+        pending: false,
+        is_request,
+        is_response,
+    }
+}
+
+impl<S, Q, R> Stream for ResponseTimeout<S, Q, R>
+where
+    S: Stream,
+    Q: FnMut(&S::Item) -> bool,
+    R: FnMut(&S::Item) -> bool,
+{
+    type Item = S::Item;
+    type Error = TimeoutError<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        // Check the timer before the stream, so a stream that is always
+        // ready can't keep it from firing. Its readiness is only delivered
+        // when the reactor runs, so the deadline is checked directly too.
+        if self.pending {
+            let elapsed = match self.delay.poll() {
+                Ok(Async::Ready(())) => true,
+                Ok(Async::NotReady) => Instant::now() >= self.delay.deadline(),
+                Err(err) => return Err(TimeoutError::Timer(err)),
+            };
+            if elapsed {
+                self.pending = false;
+                return Err(TimeoutError::Elapsed);
+            }
+        }
+        match self.stream.poll() {
+            Ok(Async::Ready(Some(item))) => {
+                if (self.is_response)(&item) {
+                    self.pending = false;
+                } else if !self.pending && (self.is_request)(&item) {
+                    self.delay.reset(Instant::now() + self.timeout);
+                    self.pending = true;
+                }
+                Ok(Async::Ready(Some(item)))
+            }
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => Err(TimeoutError::Inner(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interval;
+    use tokio::runtime::current_thread::Runtime;
+
+    fn toucher(progress: &ProgressHandle, touches: usize) -> impl Future<Item = (), Error = ()> {
+        let progress = progress.clone();
+        Interval::new_interval(Duration::from_millis(10))
+            .unwrap()
+            .take(touches as u64)
+            .for_each(move |_| {
+                progress.touch();
+                Ok(())
+            })
+            .map_err(|err| panic!("{:?}", err))
+    }
+
+    #[test]
+    fn idle_deadline_extended_by_progress() {
+        let mut rt = Runtime::new().unwrap();
+        let progress = ProgressHandle::new();
+        let op = Delay::new(Instant::now() + Duration::from_millis(100)).unwrap();
+        let guarded = with_idle_deadline(op, Duration::from_millis(30), &progress).unwrap();
+        rt.spawn(toucher(&progress, 15));
+        assert!(rt.block_on(guarded).is_ok());
+    }
+
+    #[test]
+    fn idle_deadline_fires_after_gap() {
+        let mut rt = Runtime::new().unwrap();
+        let progress = ProgressHandle::new();
+        let now = Instant::now();
+        let op = Delay::new(now + Duration::from_millis(200)).unwrap();
+        let guarded = with_idle_deadline(op, Duration::from_millis(30), &progress).unwrap();
+        rt.spawn(toucher(&progress, 3));
+        match rt.block_on(guarded) {
+            Err(TimeoutError::Elapsed) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let elapsed = now.elapsed();
+        println!("{:?}", elapsed);
+        assert!(elapsed >= Duration::from_millis(60));
+        assert!(elapsed < Duration::from_millis(200));
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Message {
+        Request,
+        Response,
+        Noise,
+    }
+
+    /// A request followed by a response `gap` later.
+    fn exchange(gap: Duration) -> impl Stream<Item = Message, Error = IoError> {
+        let mut sent = 0;
+        Interval::new_interval(gap).unwrap().take(2).map(move |_| {
+            sent += 1;
+            if sent == 1 {
+                Message::Request
+            } else {
+                Message::Response
+            }
+        })
+    }
+
+    fn guarded(
+        gap: Duration,
+        timeout: Duration,
+    ) -> impl Stream<Item = Message, Error = TimeoutError<IoError>> {
+        response_timeout(
+            exchange(gap),
+            timeout,
+            |msg| *msg == Message::Request,
+            |msg| *msg == Message::Response,
+        )
+    }
+
+    #[test]
+    fn response_timeout_fires_on_late_response() {
+        let mut rt = Runtime::new().unwrap();
+        let guarded = guarded(Duration::from_millis(50), Duration::from_millis(10));
+        match rt.block_on(guarded.collect()) {
+            Err(TimeoutError::Elapsed) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn response_timeout_passes_timely_response() {
+        let mut rt = Runtime::new().unwrap();
+        let guarded = guarded(Duration::from_millis(10), Duration::from_millis(50));
+        let messages = rt.block_on(guarded.collect()).unwrap();
+        assert_eq!(messages, vec![Message::Request, Message::Response]);
+    }
+
+    #[test]
+    fn response_timeout_fires_next_to_always_ready_stream() {
+        let mut rt = Runtime::new().unwrap();
+        let noise = futures::stream::once(Ok(Message::Request))
+            .chain(futures::stream::repeat::<_, IoError>(Message::Noise));
+        let guarded = response_timeout(
+            noise,
+            Duration::from_millis(20),
+            |msg| *msg == Message::Request,
+            |msg| *msg == Message::Response,
+        );
+        match rt.block_on(guarded.for_each(|_| Ok(()))) {
+            Err(TimeoutError::Elapsed) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn catch_unwind_reports_inner_panic() {
+        let mut rt = Runtime::new().unwrap();
+        let panicking = futures::future::poll_fn(|| -> Poll<(), ()> { panic!("boom") });
+        let timeout = Timeout::new(panicking, Duration::from_secs(1))
+            .unwrap()
+            .catch_unwind();
+        match rt.block_on(timeout) {
+            Err(TimeoutError::Panicked(payload)) => {
+                assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn catch_unwind_still_times_out() {
+        let mut rt = Runtime::new().unwrap();
+        let op = futures::future::empty::<(), ()>();
+        let timeout = Timeout::new(op, Duration::from_millis(10))
+            .unwrap()
+            .catch_unwind();
+        match rt.block_on(timeout) {
+            Err(TimeoutError::Elapsed) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use crate::{duration_from_nanos, ClockId, TimerFd};
+use futures::{task, Async, Future, Poll, Stream};
+use slab::Slab;
+use std::collections::BTreeMap;
+use std::io::Error as IoError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use timerfd::{SetTimeFlags, TimerState};
+
+struct Entry {
+    fired: bool,
+    task: Option<task::Task>,
+    inserted: Instant,
+    tag: Option<String>,
+}
+
+/// A snapshot of a pending timer of a `TimerWheel`.
+///
+/// Returned by `TimerWheel::debug_dump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimerInfo {
+    /// The instant the timer fires at next.
+    pub deadline: Instant,
+    /// The instant the timer was created.
+    pub inserted: Instant,
+    /// The tag given to `TimerWheel::insert_tagged`, if any.
+    pub tag: Option<String>,
+}
+
+struct Inner {
+    timerfd: TimerFd,
+    entries: Slab<Entry>,
+    /// Pending entries grouped by deadline, so timers sharing a deadline are
+    /// armed for and fired together.
+    queue: BTreeMap<Instant, Vec<usize>>,
+    armed: Option<Instant>,
+}
+
+impl Inner {
+    fn insert(&mut self, deadline: Instant, tag: Option<String>) -> usize {
+        let key = self.entries.insert(Entry {
+            fired: false,
+            task: None,
+            inserted: Instant::now(),
+            tag,
+        });
+        self.enqueue(key, deadline);
+        key
+    }
+
+    fn enqueue(&mut self, key: usize, deadline: Instant) {
+        self.queue.entry(deadline).or_default().push(key);
+        self.arm(Instant::now());
+    }
+
+    fn remove(&mut self, key: usize, deadline: Instant) {
+        self.entries.remove(key);
+        if let Some(keys) = self.queue.get_mut(&deadline) {
+            if let Some(pos) = keys.iter().position(|&k| k == key) {
+                keys.swap_remove(pos);
+                if keys.is_empty() {
+                    self.queue.remove(&deadline);
+                    self.arm(Instant::now());
+                }
+            }
+        }
+        // The removed entry may have belonged to the task the reactor wakes
+        // up, even if it already fired and left the queue; hand the timerfd
+        // over to the next entry in line.
+        self.notify_head();
+    }
+
+    fn reschedule(&mut self, key: usize, deadline: Instant) {
+        self.entries[key].fired = false;
+        self.enqueue(key, deadline);
+    }
+
+    /// Fires every entry that is due and re-arms the timerfd for the rest.
+    ///
+    /// Polling the timerfd registers the current task with the reactor, so
+    /// the task awaiting the earliest deadline is the one that drives the
+    /// wheel.
+    fn process(&mut self) -> Result<(), IoError> {
+        if self.timerfd.poll_read()?.is_ready() {
+            self.armed = None;
+        }
+        let now = Instant::now();
+        let mut fired = false;
+        while let Some(due) = self.queue.first_entry() {
+            if *due.key() > now {
+                break;
+            }
+            for key in due.remove() {
+                let entry = &mut self.entries[key];
+                entry.fired = true;
+                if let Some(task) = entry.task.take() {
+                    task.notify();
+                }
+            }
+            fired = true;
+        }
+        self.arm(now);
+        if fired {
+            self.notify_head();
+        }
+        Ok(())
+    }
+
+    fn notify_head(&self) {
+        if let Some((_, keys)) = self.queue.iter().next() {
+            let mut tasks = keys
+                .iter()
+                .filter_map(|&key| self.entries[key].task.as_ref());
+            if let Some(task) = tasks.next() {
+                task.notify();
+            }
+        }
+    }
+
+    fn arm(&mut self, now: Instant) {
+        let next = self.queue.keys().next().cloned();
+        if next == self.armed {
+            return;
+        }
+        let state = match next {
+            // A zero duration would disarm the timer, so overdue entries are
+            // armed to fire right away instead.
+            Some(deadline) if deadline > now => TimerState::Oneshot(deadline - now),
+            Some(_) => TimerState::Oneshot(Duration::from_nanos(1)),
+            None => TimerState::Disarmed,
+        };
+        self.timerfd.set_state(state, SetTimeFlags::Default);
+        self.armed = next;
+    }
+
+    fn poll_entry(&mut self, key: usize) -> Result<bool, IoError> {
+        self.process()?;
+        let entry = &mut self.entries[key];
+        if !entry.fired {
+            entry.task = Some(task::current());
+            return Ok(false);
+        }
+        // Polling registered this task with the reactor, which a fired entry
+        // doesn't need; hand the timerfd back to the next entry in line.
+        self.notify_head();
+        Ok(true)
+    }
+}
+
+/// A set of timers sharing a single timerfd.
+///
+/// Every `Delay` and `Interval` owns a file descriptor, which adds up for
+/// servers with thousands of timers. Timers created through a `TimerWheel`
+/// are kept in one queue and the timerfd is armed for the earliest of them.
+///
+/// `TimerWheel` is a cheap handle, clones refer to the same set of timers.
+#[derive(Clone)]
+pub struct TimerWheel {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl TimerWheel {
+    /// Create a new, empty, `TimerWheel`.
+    pub fn new() -> Result<TimerWheel, IoError> {
+        let timerfd = TimerFd::new(ClockId::Monotonic)?;
+        Ok(TimerWheel {
+            inner: Arc::new(Mutex::new(Inner {
+                timerfd,
+                entries: Slab::new(),
+                queue: BTreeMap::new(),
+                armed: None,
+            })),
+        })
+    }
+
+    /// Create a future that completes at `deadline`.
+    pub fn delay(&self, deadline: Instant) -> WheelDelay {
+        self.insert(deadline, None)
+    }
+
+    /// Create a future that completes at `deadline`, labeled with `tag` in
+    /// `TimerWheel::debug_dump`.
+    pub fn insert_tagged<T: Into<String>>(&self, deadline: Instant, tag: T) -> WheelDelay {
+        self.insert(deadline, Some(tag.into()))
+    }
+
+    /// Returns a snapshot of the pending timers, sorted by deadline.
+    ///
+    /// Timers that have fired but not been dropped aren't included, an
+    /// interval is listed with the deadline of its next tick.
+    pub fn debug_dump(&self) -> Vec<TimerInfo> {
+        let inner = self.inner.lock().unwrap();
+        let mut timers = Vec::new();
+        for (&deadline, keys) in &inner.queue {
+            for &key in keys {
+                let entry = &inner.entries[key];
+                timers.push(TimerInfo {
+                    deadline,
+                    inserted: entry.inserted,
+                    tag: entry.tag.clone(),
+                });
+            }
+        }
+        timers
+    }
+
+    fn insert(&self, deadline: Instant, tag: Option<String>) -> WheelDelay {
+        let key = self.inner.lock().unwrap().insert(deadline, tag);
+        WheelDelay {
+            wheel: self.clone(),
+            key,
+            deadline,
+        }
+    }
+
+    /// Create a stream that yields every `period`, starting `period` from now.
+    ///
+    /// As with `Interval`, ticks missed by a slow consumer are coalesced and
+    /// the schedule stays aligned to the original phase.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero.
+    pub fn interval(&self, period: Duration) -> WheelInterval {
+        assert!(!period.is_zero(), "`period` must be non-zero.");
+        let deadline = Instant::now() + period;
+        let key = self.inner.lock().unwrap().insert(deadline, None);
+        WheelInterval {
+            wheel: self.clone(),
+            key,
+            deadline,
+            period,
+        }
+    }
+}
+
+/// A future that completes at a specified instant, driven by a `TimerWheel`.
+pub struct WheelDelay {
+    wheel: TimerWheel,
+    key: usize,
+    deadline: Instant,
+}
+
+impl WheelDelay {
+    /// Returns the instant at which the future will complete.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for WheelDelay {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let fired = self.wheel.inner.lock().unwrap().poll_entry(self.key)?;
+        if fired {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl Drop for WheelDelay {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.wheel.inner.lock() {
+            inner.remove(self.key, self.deadline);
+        }
+    }
+}
+
+/// A stream yielding at a fixed period, driven by a `TimerWheel`.
+pub struct WheelInterval {
+    wheel: TimerWheel,
+    key: usize,
+    deadline: Instant,
+    period: Duration,
+}
+
+impl Stream for WheelInterval {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut inner = self.wheel.inner.lock().unwrap();
+        if !inner.poll_entry(self.key)? {
+            return Ok(Async::NotReady);
+        }
+        let now = Instant::now();
+        let periods = (now - self.deadline).as_nanos() / self.period.as_nanos() + 1;
+        self.deadline += duration_from_nanos(self.period.as_nanos() * periods);
+        inner.reschedule(self.key, self.deadline);
+        Ok(Async::Ready(Some(())))
+    }
+}
+
+impl Drop for WheelInterval {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.wheel.inner.lock() {
+            inner.remove(self.key, self.deadline);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn wheel_delays_fire_in_order() {
+        let wheel = TimerWheel::new().unwrap();
+        let now = Instant::now();
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let mut rt = Runtime::new().unwrap();
+        for i in [3u64, 1, 4, 2].iter().cloned() {
+            let fired = fired.clone();
+            let delay = wheel.delay(now + Duration::from_millis(i * 10));
+            rt.spawn(
+                delay
+                    .map(move |_| fired.lock().unwrap().push(i))
+                    .map_err(|err| panic!("{:?}", err)),
+            );
+        }
+        rt.run().unwrap();
+        assert_eq!(*fired.lock().unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn wheel_drives_many_intervals_on_one_fd() {
+        let period = Duration::from_millis(10);
+        crate::syscalls::take();
+        let wheel = TimerWheel::new().unwrap();
+        let ticks = Arc::new(Mutex::new(Vec::new()));
+        let mut rt = Runtime::new().unwrap();
+        let start = Instant::now();
+        for _ in 0..1000 {
+            let ticks = ticks.clone();
+            let interval = wheel.interval(period);
+            rt.spawn(
+                interval
+                    .take(3)
+                    .map(|_| Instant::now())
+                    .collect()
+                    .map(move |instants| ticks.lock().unwrap().push(instants))
+                    .map_err(|err| panic!("{:?}", err)),
+            );
+        }
+        rt.run().unwrap();
+        assert_eq!(crate::syscalls::take().create, 1);
+
+        let ticks = ticks.lock().unwrap();
+        assert_eq!(ticks.len(), 1000);
+        let mut total = Duration::ZERO;
+        for instants in ticks.iter() {
+            for (n, tick) in instants.iter().enumerate() {
+                assert!(*tick >= start + period * (n as u32 + 1));
+            }
+            total += instants[2] - instants[0];
+        }
+        // Single ticks are delayed under load, bound the average spacing.
+        let spacing = total / 2000;
+        assert!(spacing > period / 2, "{:?}", spacing);
+        assert!(spacing < period * 3, "{:?}", spacing);
+    }
+
+    #[test]
+    fn dropping_fired_delay_hands_timer_back() {
+        let wheel = TimerWheel::new().unwrap();
+        let now = Instant::now();
+        let mut first = Some(wheel.delay(now + Duration::from_millis(10)));
+        let mut second = wheel.delay(now + Duration::from_millis(40));
+        let guard = crate::Delay::new(now + Duration::from_millis(500)).unwrap();
+        let second_polls = Arc::new(Mutex::new(0));
+        let fired = Arc::new(Mutex::new(None));
+        let mut rt = Runtime::new().unwrap();
+        let (polls, second_fired) = (second_polls.clone(), fired.clone());
+        let second = futures::future::poll_fn(move || {
+            *polls.lock().unwrap() += 1;
+            second.poll()
+        });
+        rt.spawn(
+            second
+                .map(move |()| *second_fired.lock().unwrap() = Some(Instant::now()))
+                .select(guard)
+                .map(|_| ())
+                .map_err(|_| panic!("timer failed")),
+        );
+        rt.spawn(futures::future::poll_fn(move || {
+            let delay = first.as_mut().unwrap();
+            futures::try_ready!(delay.poll().map_err(|_| ()));
+            // Wait for the task of `second` to take over the timerfd once
+            // `first` fired, then poll the fired delay again to take it back.
+            if *second_polls.lock().unwrap() < 2 {
+                task::current().notify();
+                return Ok(Async::NotReady);
+            }
+            assert!(delay.poll().unwrap().is_ready());
+            first = None;
+            Ok(Async::Ready(()))
+        }));
+        rt.run().unwrap();
+        let fired = fired.lock().unwrap().expect("second delay didn't fire");
+        assert!(
+            fired - now < Duration::from_millis(300),
+            "{:?}",
+            fired - now
+        );
+    }
+
+    #[test]
+    fn wheel_coalesces_identical_deadlines() {
+        let wheel = TimerWheel::new().unwrap();
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let fired = Arc::new(Mutex::new(0));
+        let mut rt = Runtime::new().unwrap();
+        crate::syscalls::take();
+        let mut delays: Vec<_> = (0..1000).map(|_| wheel.delay(deadline)).collect();
+        // Dropping one timer must not affect the others on its deadline.
+        delays.pop();
+        for delay in delays {
+            let fired = fired.clone();
+            rt.spawn(
+                delay
+                    .map(move |_| *fired.lock().unwrap() += 1)
+                    .map_err(|err| panic!("{:?}", err)),
+            );
+        }
+        rt.run().unwrap();
+        assert_eq!(*fired.lock().unwrap(), 999);
+        let counts = crate::syscalls::take();
+        assert_eq!(counts.settime, 1);
+        assert_eq!(counts.read, 1);
+        assert_eq!(counts.epoll_ctl, 1);
+    }
+
+    #[test]
+    fn debug_dump_lists_tagged_timers_by_deadline() {
+        let wheel = TimerWheel::new().unwrap();
+        let now = Instant::now();
+        let before = Instant::now();
+        let _late = wheel.insert_tagged(now + Duration::from_secs(3), "late");
+        let _untagged = wheel.delay(now + Duration::from_secs(2));
+        let _early = wheel.insert_tagged(now + Duration::from_secs(1), String::from("early"));
+        let dropped = wheel.insert_tagged(now + Duration::from_secs(4), "dropped");
+        drop(dropped);
+
+        let dump = wheel.debug_dump();
+        let tags: Vec<_> = dump.iter().map(|info| info.tag.as_deref()).collect();
+        assert_eq!(tags, vec![Some("early"), None, Some("late")]);
+        let deadlines: Vec<_> = dump.iter().map(|info| info.deadline).collect();
+        assert_eq!(
+            deadlines,
+            vec![1, 2, 3]
+                .into_iter()
+                .map(|secs| now + Duration::from_secs(secs))
+                .collect::<Vec<_>>()
+        );
+        assert!(dump.iter().all(|info| info.inserted >= before));
+    }
+}